@@ -0,0 +1,31 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lock_contention::poisson_process::{duration_until_next_event, duration_until_next_event_with};
+use rand::{rngs::StdRng, SeedableRng};
+
+fn bench_next_event(c: &mut Criterion) {
+    let lambda = 1.0 / 2.0;
+    let mut group = c.benchmark_group("duration_until_next_event");
+
+    // Looks up the thread-local rng on every call
+    group.bench_function("thread_rng", |b| {
+        b.iter(|| duration_until_next_event(black_box(lambda)))
+    });
+    group.bench_function("with_thread_rng_each_call", |b| {
+        b.iter(|| duration_until_next_event_with(&mut rand::thread_rng(), black_box(lambda)))
+    });
+    group.bench_function("with_cached_thread_rng", |b| {
+        let mut rng = rand::thread_rng();
+        b.iter(|| duration_until_next_event_with(&mut rng, black_box(lambda)))
+    });
+    group.bench_function("with_cached_std_rng", |b| {
+        let mut rng = StdRng::seed_from_u64(42);
+        b.iter(|| duration_until_next_event_with(&mut rng, black_box(lambda)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_next_event);
+criterion_main!(benches);
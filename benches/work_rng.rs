@@ -0,0 +1,39 @@
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use lock_contention::poisson_process::duration_until_next_event_with;
+use rand::{
+    rngs::{SmallRng, StdRng},
+    Rng, SeedableRng,
+};
+
+/// The draws of one inner step of a task, the bulk of its cost
+fn draws(rng: &mut impl Rng, lambda: f64) {
+    black_box(duration_until_next_event_with(rng, lambda));
+    black_box(duration_until_next_event_with(rng, lambda));
+    black_box(rng.gen::<usize>());
+}
+
+/// One benchmark per [`lock_contention::lock_emulation::WorkRng`]
+fn bench_work_rng(c: &mut Criterion) {
+    let lambda = 1.0 / 2.0;
+    let mut group = c.benchmark_group("work_rng");
+
+    group.bench_function("thread", |b| {
+        let mut rng = rand::thread_rng();
+        b.iter(|| draws(&mut rng, black_box(lambda)))
+    });
+    group.bench_function("small", |b| {
+        let mut rng = SmallRng::from_entropy();
+        b.iter(|| draws(&mut rng, black_box(lambda)))
+    });
+    group.bench_function("std", |b| {
+        let mut rng = StdRng::from_entropy();
+        b.iter(|| draws(&mut rng, black_box(lambda)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_work_rng);
+criterion_main!(benches);
@@ -0,0 +1,124 @@
+//! Checks that a machine measures what it seems to measure
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    config::SimConfig,
+    lock_emulation::{do_tasks, RunResult},
+    summary::summarize,
+};
+
+/// Tasks run between clock reads in [`calibrate_task_ns`]
+const CALIBRATION_BATCH: usize = 1024;
+
+/// Mean cost in nanoseconds of one task of the built-in busy work on this machine
+///
+/// Runs batches of tasks on the calling thread, without any lock, until `duration` has elapsed.
+/// The cost of a task does not depend on the rates, so none are taken.
+pub fn calibrate_task_ns(duration: Duration) -> f64 {
+    let mut rng = rand::thread_rng();
+    let mut tasks = 0;
+    let start = Instant::now();
+    loop {
+        do_tasks(&mut rng, CALIBRATION_BATCH, 1.0 / 2.0, 1.0 / 2.0);
+        tasks += CALIBRATION_BATCH;
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            return elapsed.as_nanos() as f64 / tasks as f64;
+        }
+    }
+}
+
+/// The fraction of wall time `result` spent doing tasks, given the cost of a task from [`calibrate_task_ns`]
+///
+/// `tasks_per_sec * task_ns / 1e9`; unlike tasks/sec it can be compared across machines.
+/// `1.0` is a run that did nothing but tasks; the rest went to the lock and the phase bookkeeping.
+pub fn normalized_throughput(result: &RunResult, task_ns: f64) -> f64 {
+    result.tasks_per_sec() * task_ns / 1e9
+}
+
+/// Early-to-steady ratio above which [`detect_frequency_scaling`] warns
+pub const FREQUENCY_SCALING_THRESHOLD: f64 = 1.05;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrequencyScaling {
+    /// Aggregate tasks/sec of the short window at the start
+    pub early_tasks_per_sec: f64,
+    /// Aggregate tasks/sec of the full-length window right after it
+    pub steady_tasks_per_sec: f64,
+    /// `early_tasks_per_sec / steady_tasks_per_sec`
+    pub ratio: f64,
+}
+
+/// Compare the throughput of a cold start against a sustained run of `config`
+///
+/// The early window lasts a tenth of [`SimConfig::duration_limit`] and the steady window the full limit right after it.
+/// A ratio above [`FREQUENCY_SCALING_THRESHOLD`] suggests a boost clock inflating short runs, and a warning is printed to stderr.
+/// This is a heuristic: noise and cache warm-up move the ratio too.
+pub fn detect_frequency_scaling(config: &SimConfig) -> FrequencyScaling {
+    let early = SimConfig {
+        duration_limit: config.duration_limit / 10,
+        ..config.clone()
+    };
+    let early_tasks_per_sec = summarize(&early.run(&Mutex::new(()))).tasks_per_sec;
+    let steady_tasks_per_sec = summarize(&config.run(&Mutex::new(()))).tasks_per_sec;
+    let ratio = early_tasks_per_sec / steady_tasks_per_sec;
+    if ratio > FREQUENCY_SCALING_THRESHOLD {
+        eprintln!(
+            "warning: the early window ran {ratio:.2}x faster than the steady one ({early_tasks_per_sec:.0} vs {steady_tasks_per_sec:.0} tasks/sec); frequency scaling may inflate short runs"
+        );
+    }
+    FrequencyScaling {
+        early_tasks_per_sec,
+        steady_tasks_per_sec,
+        ratio,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lock_emulation::toggle_lock;
+
+    use super::*;
+
+    #[test]
+    fn frequency_scaling() {
+        let config = SimConfig::new(1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(500));
+        let scaling = detect_frequency_scaling(&config);
+        println!("{scaling:?}");
+        assert!(scaling.early_tasks_per_sec > 0.);
+        assert!(scaling.steady_tasks_per_sec > 0.);
+        assert_eq!(
+            scaling.ratio,
+            scaling.early_tasks_per_sec / scaling.steady_tasks_per_sec
+        );
+    }
+
+    #[test]
+    fn normalized() {
+        let result = RunResult {
+            tasks_done: 1_000_000,
+            elapsed: Duration::from_secs(2),
+            ..Default::default()
+        };
+        // 500k tasks/sec at 1 µs each is half of the time
+        assert_eq!(normalized_throughput(&result, 1000.), 0.5);
+    }
+
+    #[test]
+    fn calibrated_run() {
+        let task_ns = calibrate_task_ns(Duration::from_millis(100));
+        println!("Task: {task_ns:.0} ns");
+        assert!(task_ns > 0.);
+
+        let lock = Mutex::new(());
+        let res = toggle_lock(&lock, 1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(200));
+        let normalized = normalized_throughput(&res, task_ns);
+        println!("Normalized throughput: {normalized:.3}");
+        // A single thread is busy with tasks most of the time
+        assert!(0.5 < normalized && normalized < 1.5);
+    }
+}
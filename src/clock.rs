@@ -0,0 +1,70 @@
+//! Where runs read the time that their duration limit is checked against
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, Instant},
+};
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// [`Instant::now`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+    #[inline]
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves when [`MockClock::advance`] is called, for deterministic tests of time-based logic
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    offset_nanos: AtomicU64,
+}
+
+impl MockClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            offset_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.offset_nanos
+            .fetch_add(by.as_nanos() as u64, Ordering::Release);
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.offset_nanos.load(Ordering::Acquire))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_only_moves_when_advanced() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        std::thread::sleep(Duration::from_millis(5));
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(3));
+        assert_eq!(clock.now() - start, Duration::from_secs(3));
+        assert!(RealClock.now() >= start);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{sync::Mutex, time::Duration};
+
+use crate::lock_emulation::{
+    generate_trace, toggle_lock_parallel_with_options, toggle_lock_replay_parallel_with_options,
+    RunResult, ToggleOptions, WorkRng,
+};
+
+/// The parameters of one experiment
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimConfig {
+    pub lambda_unlock: f64,
+    pub lambda_lock: f64,
+    pub threads: usize,
+    pub duration_limit: Duration,
+    /// Replay a trace generated from this seed instead of sampling freshly
+    pub seed: Option<u64>,
+    /// See [`ToggleOptions::work_rng`]
+    pub work_rng: WorkRng,
+}
+
+impl SimConfig {
+    /// One thread and no seed
+    pub fn new(lambda_unlock: f64, lambda_lock: f64, duration_limit: Duration) -> Self {
+        Self {
+            lambda_unlock,
+            lambda_lock,
+            threads: 1,
+            duration_limit,
+            seed: None,
+            work_rng: WorkRng::default(),
+        }
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn work_rng(mut self, work_rng: WorkRng) -> Self {
+        self.work_rng = work_rng;
+        self
+    }
+
+    pub fn run(&self, lock: &Mutex<()>) -> Vec<RunResult> {
+        let options = ToggleOptions {
+            work_rng: self.work_rng,
+            ..Default::default()
+        };
+        match self.seed {
+            Some(seed) => {
+                let trace = generate_trace(
+                    self.lambda_unlock,
+                    self.lambda_lock,
+                    self.duration_limit,
+                    seed,
+                );
+                toggle_lock_replay_parallel_with_options(lock, &trace, self.threads, &options)
+            }
+            None => toggle_lock_parallel_with_options(
+                lock,
+                self.lambda_unlock,
+                self.lambda_lock,
+                self.duration_limit,
+                self.threads,
+                &options,
+            ),
+        }
+    }
+
+    /// A compact one-line description, e.g. `λ_unlock=0.50 λ_lock=0.50 threads=2 dur=3.0s seed=42`
+    ///
+    /// The seed and the work RNG are only mentioned if set, e.g. `... rng=small`.
+    pub fn describe(&self) -> String {
+        let mut description = format!(
+            "λ_unlock={:.2} λ_lock={:.2} threads={} dur={:.1}s",
+            self.lambda_unlock,
+            self.lambda_lock,
+            self.threads,
+            self.duration_limit.as_secs_f64()
+        );
+        if let Some(seed) = self.seed {
+            description.push_str(&format!(" seed={seed}"));
+        }
+        if self.work_rng != WorkRng::default() {
+            description.push_str(&format!(" rng={}", self.work_rng.name()));
+        }
+        description
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe() {
+        let config = SimConfig::new(0.5, 0.5, Duration::from_secs(3))
+            .threads(2)
+            .seed(42);
+        assert_eq!(
+            config.describe(),
+            "λ_unlock=0.50 λ_lock=0.50 threads=2 dur=3.0s seed=42"
+        );
+
+        let config = SimConfig::new(0.25, 1.0, Duration::from_millis(1500));
+        assert_eq!(
+            config.describe(),
+            "λ_unlock=0.25 λ_lock=1.00 threads=1 dur=1.5s"
+        );
+
+        let config = config.work_rng(WorkRng::Small);
+        assert_eq!(
+            config.describe(),
+            "λ_unlock=0.25 λ_lock=1.00 threads=1 dur=1.5s rng=small"
+        );
+    }
+
+    #[test]
+    fn every_work_rng() {
+        for work_rng in [WorkRng::Thread, WorkRng::Small, WorkRng::Std] {
+            let config = SimConfig::new(0.5, 0.5, Duration::from_millis(100)).work_rng(work_rng);
+            let res = config.run(&Mutex::new(()));
+            println!(
+                "{}: {:.0} tasks/sec",
+                config.describe(),
+                res[0].tasks_per_sec()
+            );
+            assert!(res[0].tasks_done > 0);
+        }
+    }
+}
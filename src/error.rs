@@ -0,0 +1,79 @@
+use std::{fmt, io};
+
+use crate::{lock_emulation::ParallelError, poisson_process::PoissonError};
+
+/// Any error of the fallible functions of this crate
+#[derive(Debug)]
+pub enum Error {
+    Poisson(PoissonError),
+    Parallel(ParallelError),
+    Io(io::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Poisson(e) => write!(f, "poisson process: {e}"),
+            Error::Parallel(e) => write!(f, "parallel run: {e}"),
+            Error::Io(e) => write!(f, "io: {e}"),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(e) => write!(f, "sqlite: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Poisson(e) => Some(e),
+            Error::Parallel(e) => Some(e),
+            Error::Io(e) => Some(e),
+            #[cfg(feature = "sqlite")]
+            Error::Sqlite(e) => Some(e),
+        }
+    }
+}
+
+impl From<PoissonError> for Error {
+    fn from(e: PoissonError) -> Self {
+        Error::Poisson(e)
+    }
+}
+
+impl From<ParallelError> for Error {
+    fn from(e: ParallelError) -> Self {
+        Error::Parallel(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Sqlite(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn wraps_sources() {
+        let e = Error::from(PoissonError::InvalidLambda(-1.));
+        assert_eq!(e.to_string(), "poisson process: invalid lambda: -1");
+        assert!(e.source().is_some());
+
+        let e = Error::from(io::Error::other("disk full"));
+        assert_eq!(e.to_string(), "io: disk full");
+    }
+}
@@ -1,2 +1,20 @@
+pub mod calibration;
+pub mod clock;
+pub mod config;
+pub mod error;
 pub mod lock_emulation;
+pub mod lockable;
+pub mod metadata;
+#[cfg(feature = "sqlite")]
+pub mod persist;
 pub mod poisson_process;
+pub mod queue;
+pub mod report;
+pub mod semaphore;
+pub mod seq_lock;
+pub mod shared_counter;
+pub mod sim;
+pub mod stats;
+pub mod summary;
+pub mod sweep;
+pub mod ticket_lock;
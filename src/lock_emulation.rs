@@ -3,156 +3,3580 @@
 //! - blog: <https://preshing.com/20111118/locks-arent-slow-lock-contention-is/>
 
 use std::{
+    fmt,
     hint::black_box,
-    sync::Mutex,
+    panic::AssertUnwindSafe,
+    sync::{
+        atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering},
+        mpsc::{self, RecvTimeoutError},
+        Arc, Barrier, Mutex, MutexGuard, TryLockError,
+    },
     time::{Duration, Instant},
 };
 
-use rand::Rng;
+use rand::{
+    rngs::{SmallRng, StdRng},
+    Rng, SeedableRng,
+};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    clock::{Clock, RealClock},
+    error::Error,
+    lockable::Lockable,
+    metadata::RunMetadata,
+    poisson_process::{
+        check_lambda, duration_until_next_event, duration_until_next_event_nhpp,
+        duration_until_next_event_with,
+    },
+    semaphore::Semaphore,
+    stats::{EwmaThroughput, RunningStats, WaitHistogram},
+    summary::{summarize, ParallelSummary},
+};
+
+#[derive(Debug, Clone, Default)]
+pub struct ToggleOptions {
+    /// `(on, off)`: do the toggle workload for `on`, then sleep for `off`, repeating until the duration limit
+    ///
+    /// Only tasks done and time spent during `on` windows are counted.
+    pub duty_cycle: Option<(Duration, Duration)>,
+    /// Measure the time spent holding the lock into [`RunResult::held`]
+    ///
+    /// Every hold is timed anyway for [`RunResult::hold_stats`], with a clock read right before the release, so inside the critical section;
+    /// that is tens of nanoseconds per acquisition with a vDSO clock. Tracking the total only adds the holds up on top.
+    pub track_hold_time: bool,
+    /// Skip the per-task work so that only the lock/unlock cycling is measured
+    ///
+    /// Task counts are still sampled to advance the phases; see [`RunResult::acquisitions_per_sec`].
+    pub no_work: bool,
+    /// Record every lock wait into [`RunResult::waits`]
+    pub record_waits: bool,
+    /// Count how often a thread of [`toggle_lock_parallel_with_options`] reacquired the lock right after itself into [`RunResult::consecutive_reacquires`]
+    pub track_reacquires: bool,
+    /// Push the index of the thread of [`toggle_lock_parallel_with_options`] into this log on every acquisition
+    ///
+    /// The log is appended while holding the lock, so it is the exact acquisition order,
+    /// but the pushes lengthen the critical section and contend on the log.
+    /// Use it to diagnose fairness, not to measure throughput.
+    pub acquisition_order: Option<Arc<Mutex<Vec<usize>>>>,
+    /// What the duration limit is compared against
+    ///
+    /// This is also what [`RunResult::elapsed`] measures, except across duty cycle windows which stay on the wall clock.
+    pub time_source: TimeSource,
+    /// Record every phase into [`RunResult::phases`] with timestamps relative to this shared epoch
+    ///
+    /// Passing the same epoch to every thread puts all their records on one timeline.
+    pub epoch: Option<Instant>,
+    /// Rate per task of a thread ending its think time, i.e. the unlock phase, and wanting the lock again
+    ///
+    /// This decouples how busy a thread is between acquisitions from the rates of the run.
+    /// `None` keeps the unlock phase sampled from the run itself, which for a constant rate run is the same as `Some(lambda_lock)`.
+    pub think_time_lambda: Option<f64>,
+    /// Cap the task count of every phase so that a single huge sample cannot dominate the run
+    ///
+    /// Capped phases are counted in [`RunResult::clamped_phases`].
+    pub max_phase_tasks: Option<usize>,
+    /// Bucket every lock wait into [`RunResult::wait_histogram`]
+    pub wait_histogram: bool,
+    /// Rate per second of a lock phase ending, making each hold an independent exponential duration instead of a task count
+    ///
+    /// The thread does tasks while holding the lock until the sampled duration has passed.
+    /// See [`RunResult::hold_stats`] for the resulting holds.
+    pub cs_jitter_lambda: Option<f64>,
+    /// Record a checkpoint into [`RunResult::checkpoints`] every time the tasks done cross a multiple of this many tasks
+    ///
+    /// Checkpoints are taken at phase boundaries, so a phase crossing several multiples yields a single checkpoint.
+    /// Smaller intervals give a finer curve at the cost of a clock read and a push per checkpoint.
+    /// `Some(0)` is the same as `None`.
+    pub checkpoint_interval: Option<u64>,
+    /// A [`RunState`] stored as `u8`, polled at every phase boundary to pause, resume or stop the run
+    ///
+    /// Time spent paused is left out of the duration limit and [`RunResult::elapsed`] and goes to [`RunResult::paused`] instead.
+    pub run_state: Option<Arc<AtomicU8>>,
+    /// Size in bytes of a per-thread scratch buffer touched during unlock phases; `0` for none
+    ///
+    /// Every task of an unlock phase writes [`SCRATCH_LINES_PER_TASK`] cache lines, cycling through the buffer,
+    /// so a buffer larger than the cache evicts the lines the thread needs at the next acquisition.
+    /// The buffer is allocated and faulted in before the measured window.
+    pub scratch_bytes: usize,
+    /// Stop the run once it has taken `factor` times the duration limit on the wall clock
+    ///
+    /// A watchdog thread sleeps on a channel next to the run at no cost to it, and sets [`RunResult::watchdog_fired`] if it had to stop it.
+    /// The budget includes paused time.
+    /// Like any other stop, it takes effect at the next phase boundary, so it cannot free a thread blocked in the acquisition itself.
+    pub watchdog_factor: Option<f64>,
+    /// The generator drawn from by the per-task work
+    ///
+    /// The draws are most of the cost of a task, so a faster generator makes tasks lighter and the lock a larger share of the run.
+    pub work_rng: WorkRng,
+    /// Issue a [`std::hint::spin_loop`] at every step of the per-task work
+    ///
+    /// This models a thread busy-waiting rather than computing; on some CPUs the hint also lowers power draw and with it thermal throttling.
+    /// The hint has a latency of its own, up to the tens of cycles of a `pause` on recent x86, so tasks get measurably heavier and throughput drops.
+    pub spin_work: bool,
+    /// Take and drop the lock around every single task of a lock phase instead of once for the whole phase
+    ///
+    /// This models fine-grained locking: the same work in many short critical sections, with one acquisition each in [`RunResult::acquisitions`].
+    /// Each acquisition is also a phase of its own in [`RunResult::phases`].
+    /// Not applied to holds timed by [`ToggleOptions::cs_jitter_lambda`].
+    pub per_task_lock: bool,
+    /// Leave out the tasks of phases ending in this first stretch of the run
+    ///
+    /// See [`ToggleOptions::trim_tail`].
+    pub warmup: Duration,
+    /// Leave out the tasks of phases ending in this last stretch before the duration limit, e.g. to skip throttling near the end
+    ///
+    /// The run still goes on until the duration limit, but [`RunResult::tasks_done`] only counts the phases ending between [`ToggleOptions::warmup`] and `duration_limit - trim_tail`,
+    /// and [`RunResult::elapsed`] is the length of that window, so the throughput is that of the steady state.
+    /// Everything else in the result covers the whole run.
+    /// With a duty cycle, every window is trimmed on its own.
+    ///
+    /// The run panics if `warmup + trim_tail` leaves no window, i.e. is not shorter than the duration limit, or than the on time of a duty cycle.
+    pub trim_tail: Duration,
+    /// Busy-wait this long right after every acquisition, modelling the fast-path cost of a lock even when uncontended
+    ///
+    /// The cost is paid holding the lock, so it stretches holds and with them the contention.
+    /// It is kept out of the waits and summed up in [`RunResult::acquire_overhead`].
+    pub acquire_cost: Duration,
+}
+
+/// The generator of [`ToggleOptions::work_rng`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WorkRng {
+    /// [`rand::rngs::ThreadRng`]
+    #[default]
+    Thread,
+    /// [`rand::rngs::SmallRng`], the fastest of the three but not cryptographically secure
+    Small,
+    /// [`rand::rngs::StdRng`], seeded from the OS for every run
+    Std,
+}
+
+impl WorkRng {
+    pub fn name(&self) -> &'static str {
+        match self {
+            WorkRng::Thread => "thread",
+            WorkRng::Small => "small",
+            WorkRng::Std => "std",
+        }
+    }
+}
+
+/// Cache lines of the scratch buffer written per unlock task; see [`ToggleOptions::scratch_bytes`]
+pub const SCRATCH_LINES_PER_TASK: usize = 16;
+const CACHE_LINE: usize = 64;
+
+/// The scratch buffer of [`ToggleOptions::scratch_bytes`]
+struct Scratch {
+    buf: Vec<u8>,
+    cursor: usize,
+}
+
+impl Scratch {
+    fn new(bytes: usize) -> Self {
+        // `vec![1; _]` writes every page now rather than on first touch
+        Self {
+            buf: vec![1; bytes],
+            cursor: 0,
+        }
+    }
+
+    fn touch(&mut self, tasks: usize) {
+        if self.buf.is_empty() {
+            return;
+        }
+        for _ in 0..tasks * SCRATCH_LINES_PER_TASK {
+            self.buf[self.cursor] = self.buf[self.cursor].wrapping_add(1);
+            self.cursor += CACHE_LINE;
+            if self.cursor >= self.buf.len() {
+                self.cursor = 0;
+            }
+        }
+        black_box(&mut self.buf);
+    }
+}
+
+/// Control of a run through [`ToggleOptions::run_state`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum RunState {
+    Running,
+    Paused,
+    Stopped,
+}
+
+impl RunState {
+    /// `None` for a value that is not a state
+    pub fn from_u8(value: u8) -> Option<Self> {
+        [RunState::Running, RunState::Paused, RunState::Stopped]
+            .into_iter()
+            .find(|state| *state as u8 == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeSource {
+    #[default]
+    WallClock,
+    /// CPU time consumed by the thread, excluding time it was descheduled or blocked
+    #[cfg(feature = "linux")]
+    ThreadCpu,
+}
+
+struct Stopwatch<'a, C> {
+    clock: &'a C,
+    source: TimeSource,
+    start: Instant,
+    #[cfg(feature = "linux")]
+    cpu_start: Duration,
+}
+
+impl<'a, C: Clock> Stopwatch<'a, C> {
+    fn start(clock: &'a C, source: TimeSource) -> Self {
+        Self {
+            clock,
+            source,
+            start: clock.now(),
+            #[cfg(feature = "linux")]
+            cpu_start: thread_cpu_time(),
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        match self.source {
+            TimeSource::WallClock => self.clock.now().saturating_duration_since(self.start),
+            #[cfg(feature = "linux")]
+            TimeSource::ThreadCpu => thread_cpu_time() - self.cpu_start,
+        }
+    }
+}
+
+#[cfg(feature = "linux")]
+fn thread_cpu_time() -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    let ret = unsafe { libc::clock_gettime(libc::CLOCK_THREAD_CPUTIME_ID, &mut time) };
+    assert_eq!(ret, 0, "{}", std::io::Error::last_os_error());
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunResult {
+    pub tasks_done: u64,
+    pub elapsed: Duration,
+    /// The longest time spent waiting for the lock in a single acquisition
+    pub max_wait: Duration,
+    /// Total time spent holding the lock if [`ToggleOptions::track_hold_time`] is set
+    pub held: Option<Duration>,
+    /// Number of times the lock was acquired
+    pub acquisitions: u64,
+    /// Every lock wait in order if [`ToggleOptions::record_waits`] is set
+    pub waits: Vec<Duration>,
+    /// Number of acquisitions where the previous holder was this same thread if [`ToggleOptions::track_reacquires`] is set
+    ///
+    /// A fair lock shows few; a lock that lets its releaser barge back in shows many.
+    pub consecutive_reacquires: u64,
+    /// Statistics of the lock waits in nanoseconds
+    pub wait_stats: RunningStats,
+    /// Number of lock phases skipped because the lock was not acquired in time
+    pub abandoned: u64,
+    /// Every phase in order if [`ToggleOptions::epoch`] is set
+    pub phases: Vec<PhaseRecord>,
+    /// Captured at the start of the run
+    pub metadata: Option<RunMetadata>,
+    /// Number of phases whose task count was capped by [`ToggleOptions::max_phase_tasks`]
+    pub clamped_phases: u64,
+    /// The lock waits if [`ToggleOptions::wait_histogram`] is set
+    pub wait_histogram: Option<WaitHistogram>,
+    /// Statistics of the lock holds in nanoseconds
+    pub hold_stats: RunningStats,
+    /// `(elapsed, tasks_done)` at every [`ToggleOptions::checkpoint_interval`]
+    ///
+    /// Differencing consecutive checkpoints gives the throughput curve of the run.
+    pub checkpoints: Vec<(Duration, u64)>,
+    /// Time spent paused through [`ToggleOptions::run_state`]
+    pub paused: Duration,
+    /// Whether the run was stopped by [`ToggleOptions::watchdog_factor`]
+    pub watchdog_fired: bool,
+    /// Time spent on [`ToggleOptions::acquire_cost`]
+    pub acquire_overhead: Duration,
+}
+
+/// One phase of one thread, timestamped relative to [`ToggleOptions::epoch`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseRecord {
+    /// Index of the thread in a parallel run; `0` otherwise
+    pub thread: usize,
+    pub phase: Phase,
+    pub start: Duration,
+    /// When the lock was obtained; `None` for unlocked and abandoned phases
+    pub acquired: Option<Duration>,
+    /// For a locked phase, right before the lock is released
+    pub end: Duration,
+}
+
+impl PhaseRecord {
+    /// Time spent waiting for the lock; `None` for unlocked and abandoned phases
+    pub fn wait(&self) -> Option<Duration> {
+        Some(self.acquired? - self.start)
+    }
+}
+
+impl RunResult {
+    pub fn tasks_per_sec(&self) -> f64 {
+        self.tasks_done as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn acquisitions_per_sec(&self) -> f64 {
+        self.acquisitions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    /// The fraction of the run spent holding the lock
+    ///
+    /// `None` if hold time was not tracked; `0.0` for a zero-length run.
+    pub fn hold_fraction(&self) -> Option<f64> {
+        let held = self.held?;
+        if self.elapsed.is_zero() {
+            return Some(0.0);
+        }
+        Some(held.as_secs_f64() / self.elapsed.as_secs_f64())
+    }
+
+    /// Combine two consecutive segments of the same run
+    ///
+    /// Same as [`RunResult::merge_with`] with [`ElapsedMerge::Sum`].
+    pub fn merge(&self, other: &RunResult) -> RunResult {
+        self.merge_with(other, ElapsedMerge::Sum)
+    }
+
+    /// Combine two results into one
+    ///
+    /// - `tasks_done`: summed
+    /// - `elapsed`: combined by `elapsed`
+    /// - `max_wait`: the maximum of both
+    /// - `held`: summed over the results that tracked it
+    /// - `acquisitions`: summed
+    /// - `waits`: concatenated
+    /// - `consecutive_reacquires`: summed
+    /// - `wait_stats`: pooled
+    /// - `abandoned`: summed
+    /// - `phases`: concatenated
+    /// - `metadata`: the first one present
+    /// - `clamped_phases`: summed
+    /// - `wait_histogram`: merged over the results that tracked it
+    /// - `hold_stats`: pooled
+    /// - `paused`: summed
+    /// - `watchdog_fired`: whether it fired in either
+    /// - `acquire_overhead`: summed
+    /// - `checkpoints`: concatenated; with [`ElapsedMerge::Sum`] those of `other` are shifted by the elapsed time and tasks done of `self`
+    pub fn merge_with(&self, other: &RunResult, elapsed: ElapsedMerge) -> RunResult {
+        RunResult {
+            tasks_done: self.tasks_done + other.tasks_done,
+            elapsed: match elapsed {
+                ElapsedMerge::Sum => self.elapsed + other.elapsed,
+                ElapsedMerge::Max => self.elapsed.max(other.elapsed),
+            },
+            max_wait: self.max_wait.max(other.max_wait),
+            held: match (self.held, other.held) {
+                (Some(a), Some(b)) => Some(a + b),
+                (a, b) => a.or(b),
+            },
+            acquisitions: self.acquisitions + other.acquisitions,
+            waits: [self.waits.as_slice(), other.waits.as_slice()].concat(),
+            consecutive_reacquires: self.consecutive_reacquires + other.consecutive_reacquires,
+            wait_stats: self.wait_stats.merge(&other.wait_stats),
+            abandoned: self.abandoned + other.abandoned,
+            phases: [self.phases.as_slice(), other.phases.as_slice()].concat(),
+            metadata: self.metadata.clone().or_else(|| other.metadata.clone()),
+            clamped_phases: self.clamped_phases + other.clamped_phases,
+            wait_histogram: match (&self.wait_histogram, &other.wait_histogram) {
+                (Some(a), Some(b)) => Some(a.merge(b)),
+                (a, b) => a.clone().or_else(|| b.clone()),
+            },
+            hold_stats: self.hold_stats.merge(&other.hold_stats),
+            paused: self.paused + other.paused,
+            watchdog_fired: self.watchdog_fired || other.watchdog_fired,
+            acquire_overhead: self.acquire_overhead + other.acquire_overhead,
+            checkpoints: {
+                let offset = match elapsed {
+                    ElapsedMerge::Sum => (self.elapsed, self.tasks_done),
+                    ElapsedMerge::Max => (Duration::ZERO, 0),
+                };
+                let shifted = other
+                    .checkpoints
+                    .iter()
+                    .map(|&(elapsed, tasks_done)| (elapsed + offset.0, tasks_done + offset.1));
+                self.checkpoints.iter().copied().chain(shifted).collect()
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElapsedMerge {
+    /// The segments ran one after another
+    Sum,
+    /// The segments ran at the same time
+    Max,
+}
+
+pub fn toggle_lock(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+) -> RunResult {
+    toggle_lock_with_options(
+        lock,
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+pub fn toggle_lock_with_options(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    options: &ToggleOptions,
+) -> RunResult {
+    toggle(
+        || Some(lock.lock().unwrap()),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        options,
+    )
+}
+
+/// Same as [`toggle_lock`] but the duration limit and [`RunResult::elapsed`] go by `clock`
+///
+/// With a [`MockClock`](crate::clock::MockClock) the run ends exactly when the mock is advanced past the limit.
+/// [`RealClock`] gives the same run as [`toggle_lock`].
+pub fn toggle_lock_with_clock(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    clock: &impl Clock,
+) -> RunResult {
+    let mut rng = rand::thread_rng();
+    toggle_with_clock(
+        clock,
+        || Some(lock.lock().unwrap()),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        |tasks| do_tasks(&mut rng, tasks, lambda_unlock, lambda_lock),
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Same as [`toggle_lock`] but each task is one call of `work` in place of the built-in busy work
+///
+/// The return value of `work` goes through [`black_box`] so the optimizer cannot elide the work.
+/// `T` can be `()` for work that is done for its side effects.
+pub fn toggle_lock_with_work<T>(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    mut work: impl FnMut() -> T,
+) -> RunResult {
+    toggle_with_work(
+        || Some(lock.lock().unwrap()),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        |tasks| {
+            for _ in 0..tasks {
+                black_box(work());
+            }
+        },
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Same as [`toggle_lock`] but `on_acquire` is called with the wait and the time since the call every time the lock is obtained
+///
+/// `on_acquire` runs right after the acquisition, before the work of the lock phase, while the lock is held.
+/// It is part of the critical section and lengthens it, so keep it cheap.
+pub fn toggle_lock_on_acquire(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    mut on_acquire: impl FnMut(Duration, Duration),
+) -> RunResult {
+    let start = Instant::now();
+    let acquire = || {
+        let wait_start = Instant::now();
+        let guard = lock.lock().unwrap();
+        let wait = wait_start.elapsed();
+        on_acquire(wait, start.elapsed());
+        Some(guard)
+    };
+    toggle(
+        acquire,
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Same as [`toggle_lock`] but the rates vary over time
+///
+/// Time is measured in tasks done by the thread so far.
+/// The `*_max` rates must bound their `lambda_*_fn` (see [`duration_until_next_event_nhpp`]).
+pub fn toggle_lock_nhpp(
+    lock: &Mutex<()>,
+    lambda_unlock_fn: impl Fn(f64) -> f64,
+    lambda_unlock_max: f64,
+    lambda_lock_fn: impl Fn(f64) -> f64,
+    lambda_lock_max: f64,
+    duration_limit: Duration,
+) -> RunResult {
+    let next_tasks = |phase, tasks_done: u64| {
+        let duration = match phase {
+            Phase::Locked => duration_until_next_event_nhpp(
+                &lambda_unlock_fn,
+                lambda_unlock_max,
+                tasks_done as f64,
+            ),
+            Phase::Unlocked => {
+                duration_until_next_event_nhpp(&lambda_lock_fn, lambda_lock_max, tasks_done as f64)
+            }
+        };
+        (duration + 0.5) as usize
+    };
+    toggle(
+        || Some(lock.lock().unwrap()),
+        no_critical,
+        next_tasks,
+        lambda_unlock_max,
+        lambda_lock_max,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Window length of [`toggle_lock_until_stable`]
+pub const STABLE_WINDOW: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StableRun {
+    pub result: RunResult,
+    /// Whether two consecutive windows agreed before the duration ran out
+    pub converged: bool,
+}
+
+/// Same as [`toggle_lock`] but stops once the throughput estimate stabilizes
+///
+/// The run is split into windows of [`STABLE_WINDOW`].
+/// It stops when the tasks/sec of two consecutive windows differ by at most `rel_tolerance` of the larger one, or when `max_duration` is reached.
+pub fn toggle_lock_until_stable(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    rel_tolerance: f64,
+    max_duration: Duration,
+) -> StableRun {
+    let mut result = RunResult::default();
+    let mut prev_tasks_per_sec: Option<f64> = None;
+    let start = Instant::now();
+    loop {
+        let remaining = max_duration.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return StableRun {
+                result,
+                converged: false,
+            };
+        }
+        let window = toggle_lock(
+            lock,
+            lambda_unlock,
+            lambda_lock,
+            STABLE_WINDOW.min(remaining),
+        );
+        result = result.merge(&window);
+
+        let tasks_per_sec = window.tasks_per_sec();
+        if let Some(prev) = prev_tasks_per_sec {
+            if (tasks_per_sec - prev).abs() <= rel_tolerance * tasks_per_sec.max(prev) {
+                return StableRun {
+                    result,
+                    converged: true,
+                };
+            }
+        }
+        prev_tasks_per_sec = Some(tasks_per_sec);
+    }
+}
+
+/// A snapshot handed to the callback of [`toggle_lock_with_progress`] after each tick
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub elapsed: Duration,
+    /// Tasks done since the start of the run
+    pub tasks_done: u64,
+    /// Tasks/sec of the last tick alone
+    pub tasks_per_sec: f64,
+    /// Tasks/sec smoothed by [`EwmaThroughput`]
+    pub smoothed_tasks_per_sec: f64,
+}
+
+/// Same as [`toggle_lock`] but `on_tick` is called after every `tick` of the run
+///
+/// `alpha` is the smoothing factor of [`Progress::smoothed_tasks_per_sec`] (see [`EwmaThroughput::new`]).
+pub fn toggle_lock_with_progress(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    tick: Duration,
+    alpha: f64,
+    mut on_tick: impl FnMut(&Progress),
+) -> RunResult {
+    let mut ewma = EwmaThroughput::new(alpha);
+    let mut result = RunResult::default();
+    let start = Instant::now();
+    loop {
+        let remaining = duration_limit.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return result;
+        }
+        let window = toggle_lock(lock, lambda_unlock, lambda_lock, tick.min(remaining));
+        result = result.merge(&window);
+        ewma.update(window.tasks_done, window.elapsed);
+        on_tick(&Progress {
+            elapsed: start.elapsed(),
+            tasks_done: result.tasks_done,
+            tasks_per_sec: window.tasks_per_sec(),
+            smoothed_tasks_per_sec: ewma.current(),
+        });
+    }
+}
+
+/// Same as [`toggle_lock`] but the wait histogram is flushed into a snapshot every `snapshot_interval`
+///
+/// Each snapshot holds the waits of its interval alone, timestamped with the elapsed time at its end.
+pub fn toggle_lock_snapshots(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    snapshot_interval: Duration,
+) -> Vec<(Duration, WaitHistogram)> {
+    let options = ToggleOptions {
+        wait_histogram: true,
+        ..Default::default()
+    };
+    let mut snapshots = vec![];
+    let start = Instant::now();
+    loop {
+        let remaining = duration_limit.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return snapshots;
+        }
+        let window = toggle_lock_with_options(
+            lock,
+            lambda_unlock,
+            lambda_lock,
+            snapshot_interval.min(remaining),
+            &options,
+        );
+        snapshots.push((start.elapsed(), window.wait_histogram.unwrap()));
+    }
+}
+
+/// Same as [`toggle_lock`] but paused, resumed and stopped through `state`; see [`ToggleOptions::run_state`]
+pub fn toggle_lock_pausable(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    state: Arc<AtomicU8>,
+) -> RunResult {
+    let options = ToggleOptions {
+        run_state: Some(state),
+        ..Default::default()
+    };
+    toggle_lock_with_options(lock, lambda_unlock, lambda_lock, duration_limit, &options)
+}
+
+/// Window length of [`toggle_lock_pid`]
+pub const PID_WINDOW: Duration = Duration::from_millis(50);
+/// The fixed `lambda_unlock` of [`toggle_lock_pid`]; a lock phase lasts 8 tasks on average
+pub const PID_LAMBDA_UNLOCK: f64 = 1.0 / 8.0;
+/// Proportional gain of [`toggle_lock_pid`] on `ln(lambda_lock)` per unit of hold fraction error
+const PID_GAIN: f64 = 2.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PidRun {
+    pub result: RunResult,
+    /// `lambda_lock` after the last adjustment
+    pub lambda_lock: f64,
+}
+
+/// Same as [`toggle_lock`] but `lambda_lock` is adjusted on the fly to hold the lock `target_hold_fraction` of the time
+///
+/// The run is split into windows of [`PID_WINDOW`] with `lambda_unlock` fixed at [`PID_LAMBDA_UNLOCK`].
+/// After each window a proportional controller scales `lambda_lock` by `exp(gain * (target - measured))`, where `measured` is [`RunResult::hold_fraction`] of the window.
+///
+/// # Stability
+///
+/// - The gain is fixed; it converges near a hold fraction of one half and slows down toward `0` and `1` where the hold fraction barely responds to `lambda_lock`
+/// - Targets the lock cannot reach, e.g. because other threads keep it busy, make `lambda_lock` drift until the duration runs out
+/// - Each window is short, so the final `lambda_lock` still carries window-to-window noise
+pub fn toggle_lock_pid(
+    lock: &Mutex<()>,
+    target_hold_fraction: f64,
+    duration_limit: Duration,
+) -> PidRun {
+    assert!(
+        0. < target_hold_fraction && target_hold_fraction < 1.,
+        "target hold fraction must be in (0, 1)"
+    );
+    let options = ToggleOptions {
+        track_hold_time: true,
+        ..Default::default()
+    };
+    let mut lambda_lock = PID_LAMBDA_UNLOCK;
+    let mut result = RunResult::default();
+    let start = Instant::now();
+    loop {
+        let remaining = duration_limit.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            return PidRun {
+                result,
+                lambda_lock,
+            };
+        }
+        let window = toggle_lock_with_options(
+            lock,
+            PID_LAMBDA_UNLOCK,
+            lambda_lock,
+            PID_WINDOW.min(remaining),
+            &options,
+        );
+        result = result.merge(&window);
+
+        let error = target_hold_fraction - window.hold_fraction().unwrap();
+        lambda_lock = (lambda_lock * (PID_GAIN * error).exp()).clamp(1e-6, 1e6);
+    }
+}
+
+/// Same as [`toggle_lock`] but a lock phase is abandoned if the lock is not acquired within `acquire_timeout`
+///
+/// An abandoned phase skips its critical section and is counted in [`RunResult::abandoned`] instead of [`RunResult::tasks_done`].
+#[cfg(feature = "parking-lot")]
+pub fn toggle_lock_timeout(
+    lock: &parking_lot::Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    acquire_timeout: Duration,
+) -> RunResult {
+    toggle(
+        || lock.try_lock_for(acquire_timeout),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Which side of a [`parking_lot::RwLock`] wins when readers and writers contend
+#[cfg(feature = "parking-lot")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RwPreference {
+    /// Readers take [`parking_lot::RwLock::read_recursive`], which gets in even while a writer is waiting
+    Readers,
+    /// Readers take [`parking_lot::RwLock::read`], which queues behind a waiting writer
+    Writers,
+}
+
+#[cfg(feature = "parking-lot")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RwRun {
+    pub readers: ParallelSummary,
+    pub writers: ParallelSummary,
+    /// The longest wait of a single write acquisition
+    pub writer_max_wait: Duration,
+    /// Write acquisitions that waited longer than the starvation threshold
+    pub starved_writes: u64,
+}
+
+/// Run `readers` reader threads and `writers` writer threads on one [`parking_lot::RwLock`] under `preference`
+///
+/// A lock phase takes the read lock in a reader thread and the write lock in a writer thread.
+/// parking_lot has no preference switch on the lock itself; the preference comes from which read method the readers use (see [`RwPreference`]).
+#[cfg(feature = "parking-lot")]
+#[allow(clippy::too_many_arguments)]
+pub fn toggle_rwlock(
+    lock: &parking_lot::RwLock<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    readers: usize,
+    writers: usize,
+    preference: RwPreference,
+    starvation_threshold: Duration,
+) -> RwRun {
+    let results = parallel(readers + writers, |thread| {
+        let next_tasks = constant_rate(lambda_unlock, lambda_lock);
+        if thread >= readers {
+            let options = ToggleOptions {
+                record_waits: true,
+                ..Default::default()
+            };
+            return toggle(
+                || Some(lock.write()),
+                no_critical,
+                next_tasks,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                &options,
+            );
+        }
+        let options = ToggleOptions::default();
+        match preference {
+            RwPreference::Readers => toggle(
+                || Some(lock.read_recursive()),
+                no_critical,
+                next_tasks,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                &options,
+            ),
+            RwPreference::Writers => toggle(
+                || Some(lock.read()),
+                no_critical,
+                next_tasks,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                &options,
+            ),
+        }
+    });
+    let (reader_results, writer_results) = results.split_at(readers);
+    let writer_waits = writer_results.iter().flat_map(|res| &res.waits);
+    RwRun {
+        readers: summarize(reader_results),
+        writers: summarize(writer_results),
+        writer_max_wait: writer_waits.clone().max().copied().unwrap_or_default(),
+        starved_writes: writer_waits
+            .filter(|&&wait| wait > starvation_threshold)
+            .count() as u64,
+    }
+}
+
+/// [`toggle_rwlock`] under each preference, each on a fresh lock
+///
+/// Returns the runs favoring readers and favoring writers in that order.
+#[cfg(feature = "parking-lot")]
+pub fn compare_rwlock_preference(
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    readers: usize,
+    writers: usize,
+    starvation_threshold: Duration,
+) -> (RwRun, RwRun) {
+    let run = |preference| {
+        toggle_rwlock(
+            &parking_lot::RwLock::new(()),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            readers,
+            writers,
+            preference,
+            starvation_threshold,
+        )
+    };
+    (run(RwPreference::Readers), run(RwPreference::Writers))
+}
+
+/// `(lambda_unlock, lambda_lock)` for a lock held all but `release_fraction` of the time
+///
+/// `base_rate` is the rate of lock/unlock cycles, i.e. a cycle lasts `1 / base_rate` tasks on average.
+pub fn mostly_held(release_fraction: f64, base_rate: f64) -> (f64, f64) {
+    let lambda_unlock = base_rate / (1. - release_fraction);
+    let lambda_lock = base_rate / release_fraction;
+    (lambda_unlock, lambda_lock)
+}
+
+/// Run the same work loop as [`toggle_lock`] but without ever acquiring a lock
+///
+/// `lock_overhead = baseline.tasks_per_sec() / contended.tasks_per_sec()`
+pub fn uncontended_baseline(
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+) -> RunResult {
+    toggle(
+        || Some(()),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Same as [`toggle_lock`] but the lock phase holds one permit of `sem`
+///
+/// With a single-permit semaphore this is equivalent to [`toggle_lock`].
+pub fn toggle_semaphore(
+    sem: &Semaphore,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+) -> RunResult {
+    toggle_semaphore_with_options(
+        sem,
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Same as [`toggle_semaphore`] but with [`ToggleOptions`]
+pub fn toggle_semaphore_with_options(
+    sem: &Semaphore,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    options: &ToggleOptions,
+) -> RunResult {
+    toggle(
+        || Some(sem.acquire()),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        options,
+    )
+}
+
+/// A reproducible sequence of phases to drive [`toggle_lock_replay`]
+///
+/// The steps are derived from `seed` on demand, so every replay of the same trace sees the same steps no matter how many of them it gets through within `duration_limit`.
+/// They are drawn from [`ChaCha8Rng`], whose output for a seed is fixed across platforms and versions, unlike that of [`StdRng`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Trace {
+    pub lambda_unlock: f64,
+    pub lambda_lock: f64,
+    pub duration_limit: Duration,
+    pub seed: u64,
+}
+
+impl Trace {
+    /// The `(phase, tasks)` steps, starting with [`Phase::Locked`] and alternating
+    pub fn steps(&self) -> impl Iterator<Item = (Phase, usize)> {
+        let (lambda_unlock, lambda_lock) = (self.lambda_unlock, self.lambda_lock);
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let mut phase = Phase::Locked;
+        std::iter::from_fn(move || {
+            let lambda = match phase {
+                Phase::Locked => lambda_unlock,
+                Phase::Unlocked => lambda_lock,
+            };
+            let step = (
+                phase,
+                (duration_until_next_event_with(&mut rng, lambda) + 0.5) as usize,
+            );
+            phase = phase.next();
+            Some(step)
+        })
+    }
+
+    /// An independent trace with the same parameters for the `thread`-th thread of a parallel replay
+    pub fn for_thread(&self, thread: usize) -> Trace {
+        Trace {
+            seed: self.seed.wrapping_add(thread as u64),
+            ..self.clone()
+        }
+    }
+}
+
+pub fn generate_trace(
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    seed: u64,
+) -> Trace {
+    Trace {
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        seed,
+    }
+}
+
+/// Same as [`toggle_lock`] but the phases come from `trace`
+pub fn toggle_lock_replay<L: Lockable>(lock: &L, trace: &Trace) -> RunResult {
+    toggle_lock_replay_with_options(lock, trace, &ToggleOptions::default())
+}
+
+pub fn toggle_lock_replay_with_options<L: Lockable>(
+    lock: &L,
+    trace: &Trace,
+    options: &ToggleOptions,
+) -> RunResult {
+    let mut steps = trace.steps();
+    let next_tasks = |phase, _| {
+        let (step_phase, tasks) = steps.next().unwrap();
+        debug_assert_eq!(step_phase, phase);
+        tasks
+    };
+    toggle(
+        || Some(lock.acquire()),
+        no_critical,
+        next_tasks,
+        trace.lambda_unlock,
+        trace.lambda_lock,
+        trace.duration_limit,
+        options,
+    )
+}
+
+/// Same as [`toggle_lock_parallel`] but the threads are spread round-robin over the shards in `locks`
+///
+/// Thread `i` only ever takes `locks[i % locks.len()]`.
+pub fn toggle_lock_sharded(
+    locks: &[Mutex<()>],
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RunResult> {
+    parallel(threads, |thread| {
+        let lock = &locks[thread % locks.len()];
+        toggle_lock(lock, lambda_unlock, lambda_lock, duration_limit)
+    })
+}
+
+/// Same as [`toggle_lock_parallel`] but every thread has its own private lock
+///
+/// Nothing is contended, so comparing against a shared lock separates the cost of contention from the overhead of running threads side by side.
+/// See [`summarize`] for the aggregate.
+pub fn toggle_lock_independent(
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RunResult> {
+    parallel(threads, |_| {
+        toggle_lock(&Mutex::new(()), lambda_unlock, lambda_lock, duration_limit)
+    })
+}
+
+/// Panics unwound by [`toggle_lock_poisoning`]
+#[derive(Debug)]
+struct SimulatedPanic;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoisonRun {
+    pub results: Vec<RunResult>,
+    /// Critical sections that panicked and poisoned the lock
+    pub panics: u64,
+    /// Acquisitions that found the lock poisoned and cleared it
+    pub recoveries: u64,
+}
+
+/// Same as [`toggle_lock_parallel`] but a lock phase panics while holding the lock with probability `panic_probability`, poisoning it
+///
+/// The panic is caught with [`std::panic::catch_unwind`] and the thread goes on with its phase after taking the lock again.
+/// Whichever thread takes the poisoned lock next recovers it with [`Mutex::clear_poison`], so every panic is followed by exactly one recovery.
+/// The panics are raised by [`std::panic::resume_unwind`], which skips the panic hook, so nothing is printed for them.
+/// Compare against `panic_probability = 0.` for the cost of the unwinding and recovery.
+pub fn toggle_lock_poisoning(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+    panic_probability: f64,
+) -> PoisonRun {
+    let panics = AtomicU64::new(0);
+    let recoveries = AtomicU64::new(0);
+    let lock_or_recover = || match lock.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => {
+            recoveries.fetch_add(1, Ordering::Relaxed);
+            lock.clear_poison();
+            poisoned.into_inner()
+        }
+    };
+    let results = parallel(threads, |_| {
+        let mut rng = rand::thread_rng();
+        let acquire = || {
+            if rng.gen_bool(panic_probability) {
+                let guard = lock_or_recover();
+                let unwound = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    // Dropped while unwinding, which poisons the lock
+                    let _guard = guard;
+                    std::panic::resume_unwind(Box::new(SimulatedPanic));
+                }));
+                debug_assert!(unwound.is_err());
+                panics.fetch_add(1, Ordering::Relaxed);
+            }
+            Some(lock_or_recover())
+        };
+        toggle(
+            acquire,
+            no_critical,
+            constant_rate(lambda_unlock, lambda_lock),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            &ToggleOptions::default(),
+        )
+    });
+    PoisonRun {
+        results,
+        panics: panics.into_inner(),
+        recoveries: recoveries.into_inner(),
+    }
+}
+
+/// Release `threads` threads at once through a barrier to each acquire `lock` one time
+///
+/// Returns the wait of every thread in thread order.
+/// Each thread unlocks right away and exits, so the waits are the pure queueing of a thundering herd without the Poisson dynamics in between.
+pub fn contended_acquire_latency<L: Lockable + Sync>(lock: &L, threads: usize) -> Vec<Duration> {
+    let barrier = Barrier::new(threads);
+    std::thread::scope(|s| {
+        let handles = (0..threads)
+            .map(|_| {
+                s.spawn(|| {
+                    barrier.wait();
+                    let start = Instant::now();
+                    let guard = lock.acquire();
+                    let wait = start.elapsed();
+                    drop(guard);
+                    wait
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    })
+}
+
+/// Same as [`toggle_lock`] but on any [`Lockable`]
+pub fn toggle_lockable<L: Lockable>(
+    lock: &L,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+) -> RunResult {
+    toggle(
+        || Some(lock.acquire()),
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+/// Same as [`toggle_lock_parallel`] but on any [`Lockable`]
+pub fn toggle_lockable_parallel<L: Lockable + Sync>(
+    lock: &L,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RunResult> {
+    parallel(threads, |_| {
+        toggle_lockable(lock, lambda_unlock, lambda_lock, duration_limit)
+    })
+}
+
+/// Each thread replays its own [`Trace::for_thread`]
+pub fn toggle_lock_replay_parallel<L: Lockable + Sync>(
+    lock: &L,
+    trace: &Trace,
+    threads: usize,
+) -> Vec<RunResult> {
+    toggle_lock_replay_parallel_with_options(lock, trace, threads, &ToggleOptions::default())
+}
+
+pub fn toggle_lock_replay_parallel_with_options<L: Lockable + Sync>(
+    lock: &L,
+    trace: &Trace,
+    threads: usize,
+    options: &ToggleOptions,
+) -> Vec<RunResult> {
+    parallel(threads, |thread| {
+        toggle_lock_replay_with_options(lock, &trace.for_thread(thread), options)
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Lock then wait until unlock
+    Locked,
+    /// Unlock then wait until lock
+    Unlocked,
+}
+
+impl Phase {
+    fn next(self) -> Self {
+        match self {
+            Phase::Locked => Phase::Unlocked,
+            Phase::Unlocked => Phase::Locked,
+        }
+    }
+}
+
+/// Busy work of `tasks` tasks
+pub(crate) fn do_tasks(rng: &mut impl Rng, tasks: usize, lambda_unlock: f64, lambda_lock: f64) {
+    do_tasks_with(rng, tasks, lambda_unlock, lambda_lock, false);
+}
+
+/// Same as [`do_tasks`] but with a [`std::hint::spin_loop`] per inner step if `spin` is set
+fn do_tasks_with(
+    rng: &mut impl Rng,
+    tasks: usize,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    spin: bool,
+) {
+    // Each task should be significantly heavier than the control flow overhead
+    const TASK_INNER_LOOP: usize = 128;
+
+    for _ in 0..tasks {
+        for _ in 0..TASK_INNER_LOOP {
+            black_box(duration_until_next_event_with(rng, lambda_unlock));
+            black_box(duration_until_next_event_with(rng, lambda_lock));
+            black_box(rng.gen::<usize>());
+            if spin {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// A `critical` for [`toggle`] that does nothing
+pub(crate) fn no_critical<G>(_: &mut G, _: usize) {}
+
+pub(crate) fn constant_rate(
+    lambda_unlock: f64,
+    lambda_lock: f64,
+) -> impl FnMut(Phase, u64) -> usize {
+    move |phase, _| {
+        let lambda = match phase {
+            Phase::Locked => lambda_unlock,
+            Phase::Unlocked => lambda_lock,
+        };
+        (duration_until_next_event(lambda) + 0.5) as usize
+    }
+}
+
+/// - `acquire` is called at the start of each lock phase and its return value is dropped at the end of it; `None` abandons the phase
+/// - `critical` is given the guard and the task count of each lock phase to do extra work while holding the lock
+/// - `next_tasks` samples the task count of a phase given the tasks done so far
+/// - `lambda_unlock` and `lambda_lock` only feed the per-task work
+pub(crate) fn toggle<G>(
+    acquire: impl FnMut() -> Option<G>,
+    critical: impl FnMut(&mut G, usize),
+    next_tasks: impl FnMut(Phase, u64) -> usize,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    options: &ToggleOptions,
+) -> RunResult {
+    // Each arm monomorphizes the work so that the draws are not dispatched dynamically
+    match options.work_rng {
+        WorkRng::Thread => toggle_with_rng(
+            rand::thread_rng(),
+            acquire,
+            critical,
+            next_tasks,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            options,
+        ),
+        WorkRng::Small => toggle_with_rng(
+            SmallRng::from_entropy(),
+            acquire,
+            critical,
+            next_tasks,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            options,
+        ),
+        WorkRng::Std => toggle_with_rng(
+            StdRng::from_entropy(),
+            acquire,
+            critical,
+            next_tasks,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            options,
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn toggle_with_rng<G>(
+    mut rng: impl Rng,
+    acquire: impl FnMut() -> Option<G>,
+    critical: impl FnMut(&mut G, usize),
+    next_tasks: impl FnMut(Phase, u64) -> usize,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    options: &ToggleOptions,
+) -> RunResult {
+    let work = |tasks: usize| {
+        if options.no_work {
+            return;
+        }
+        do_tasks_with(
+            &mut rng,
+            tasks,
+            lambda_unlock,
+            lambda_lock,
+            options.spin_work,
+        );
+    };
+    toggle_with_work(acquire, critical, next_tasks, work, duration_limit, options)
+}
+
+/// Same as [`toggle`] but `work` does the given number of tasks in place of the built-in busy work
+pub(crate) fn toggle_with_work<G>(
+    acquire: impl FnMut() -> Option<G>,
+    critical: impl FnMut(&mut G, usize),
+    next_tasks: impl FnMut(Phase, u64) -> usize,
+    work: impl FnMut(usize),
+    duration_limit: Duration,
+    options: &ToggleOptions,
+) -> RunResult {
+    toggle_with_clock(
+        &RealClock,
+        acquire,
+        critical,
+        next_tasks,
+        work,
+        duration_limit,
+        options,
+    )
+}
+
+/// Same as [`toggle_with_work`] but the duration limit and [`RunResult::elapsed`] go by `clock`
+///
+/// Only [`TimeSource::WallClock`] reads `clock`; the waits and holds are still measured on the real clock.
+fn toggle_with_clock<G, C: Clock>(
+    clock: &C,
+    acquire: impl FnMut() -> Option<G>,
+    critical: impl FnMut(&mut G, usize),
+    next_tasks: impl FnMut(Phase, u64) -> usize,
+    work: impl FnMut(usize),
+    duration_limit: Duration,
+    options: &ToggleOptions,
+) -> RunResult {
+    let Some(factor) = options.watchdog_factor else {
+        return toggle_watched(
+            clock,
+            acquire,
+            critical,
+            next_tasks,
+            work,
+            duration_limit,
+            options,
+            None,
+        );
+    };
+    with_watchdog(duration_limit.mul_f64(factor), |watch| {
+        toggle_watched(
+            clock,
+            acquire,
+            critical,
+            next_tasks,
+            work,
+            duration_limit,
+            options,
+            Some(watch),
+        )
+    })
+}
+
+/// Same as [`toggle_with_clock`] but also stopped once `watch` is [`Watch::Fired`]
+#[allow(clippy::too_many_arguments)]
+fn toggle_watched<G, C: Clock>(
+    clock: &C,
+    mut acquire: impl FnMut() -> Option<G>,
+    mut critical: impl FnMut(&mut G, usize),
+    mut next_tasks: impl FnMut(Phase, u64) -> usize,
+    mut work: impl FnMut(usize),
+    duration_limit: Duration,
+    options: &ToggleOptions,
+    watch: Option<&AtomicU8>,
+) -> RunResult {
+    let window = options
+        .duty_cycle
+        .map_or(duration_limit, |(on, _)| on.min(duration_limit));
+    assert!(
+        (options.warmup.is_zero() && options.trim_tail.is_zero())
+            || options.warmup + options.trim_tail < window,
+        "warmup and trim_tail must leave a window"
+    );
+    let metadata = RunMetadata::capture();
+    let mut scratch = Scratch::new(options.scratch_bytes);
+    let mut next_tasks = |phase, tasks_done| {
+        // Always advance the run's own sampler so replayed traces stay aligned
+        let tasks = next_tasks(phase, tasks_done);
+        match (phase, options.think_time_lambda) {
+            (Phase::Unlocked, Some(lambda)) => (duration_until_next_event(lambda) + 0.5) as usize,
+            _ => tasks,
+        }
+    };
+    let Some((on, off)) = options.duty_cycle else {
+        let result = toggle_phases(
+            clock,
+            &mut acquire,
+            &mut critical,
+            &mut next_tasks,
+            &mut work,
+            &mut scratch,
+            duration_limit,
+            options,
+            watch,
+        );
+        return RunResult {
+            metadata: Some(metadata),
+            ..result
+        };
+    };
+
+    let mut result = RunResult {
+        metadata: Some(metadata),
+        ..Default::default()
+    };
+    let start = Stopwatch::start(clock, TimeSource::WallClock);
+    loop {
+        let remaining = duration_limit.saturating_sub(start.elapsed());
+        if remaining.is_zero() {
+            break;
+        }
+        let res = toggle_phases(
+            clock,
+            &mut acquire,
+            &mut critical,
+            &mut next_tasks,
+            &mut work,
+            &mut scratch,
+            on.min(remaining),
+            options,
+            watch,
+        );
+        result = result.merge(&res);
+
+        let remaining = duration_limit.saturating_sub(start.elapsed());
+        if remaining.is_zero() || watch.is_some_and(Watch::fired) {
+            break;
+        }
+        std::thread::sleep(off.min(remaining));
+    }
+    result
+}
+
+/// Outcome of a run under [`ToggleOptions::watchdog_factor`], decided once by whichever of the run and the watchdog gets there first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+enum Watch {
+    Running,
+    Completed,
+    Fired,
+}
+
+impl Watch {
+    fn fired(watch: &AtomicU8) -> bool {
+        watch.load(Ordering::Acquire) == Watch::Fired as u8
+    }
+}
+
+/// Call `run` with a watch of its own that fires once `budget` has passed
+///
+/// The watch is separate from [`ToggleOptions::run_state`], which is left as the caller set it.
+/// [`RunResult::watchdog_fired`] is set if the watchdog fired before the run completed.
+fn with_watchdog(budget: Duration, run: impl FnOnce(&AtomicU8) -> RunResult) -> RunResult {
+    let watch = AtomicU8::new(Watch::Running as u8);
+    let (done, dismissed) = mpsc::channel::<()>();
+    std::thread::scope(|s| {
+        let watch = &watch;
+        s.spawn(move || {
+            // Dropping `done` wakes the watchdog up right away
+            if dismissed.recv_timeout(budget) == Err(RecvTimeoutError::Timeout) {
+                let _ = watch.compare_exchange(
+                    Watch::Running as u8,
+                    Watch::Fired as u8,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                );
+            }
+        });
+        let result = run(watch);
+        let completed = watch
+            .compare_exchange(
+                Watch::Running as u8,
+                Watch::Completed as u8,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok();
+        drop(done);
+        RunResult {
+            watchdog_fired: !completed,
+            ..result
+        }
+    })
+}
+
+/// Tasks run between checks of the duration limit within a phase
+const PHASE_CHUNK_TASKS: usize = 256;
+
+/// Run `tasks` tasks through `run` in chunks, cutting the phase short once `start` passes `duration_limit`
+///
+/// Returns the number of tasks done.
+/// `run` is called at least once, even for zero tasks, so a phase of huge or infinite sampled length cannot hang the run.
+fn run_chunked(
+    tasks: usize,
+    start: &Stopwatch<impl Clock>,
+    duration_limit: Duration,
+    mut run: impl FnMut(usize),
+) -> usize {
+    let mut done = 0;
+    loop {
+        let chunk = (tasks - done).min(PHASE_CHUNK_TASKS);
+        run(chunk);
+        done += chunk;
+        if done == tasks || start.elapsed() > duration_limit {
+            return done;
+        }
+    }
+}
+
+/// Run one task at a time through `run` until `duration` has passed, cutting it short once `start` passes `duration_limit`
+///
+/// Returns the number of tasks done, zero if `duration` is over before a first task.
+/// The clock is checked before every task, so a duration shorter than a task is not stretched to a whole one.
+fn run_for(
+    duration: Duration,
+    start: &Stopwatch<impl Clock>,
+    duration_limit: Duration,
+    mut run: impl FnMut(usize),
+) -> usize {
+    let run_start = Instant::now();
+    let mut done = 0;
+    loop {
+        if run_start.elapsed() >= duration || start.elapsed() > duration_limit {
+            return done;
+        }
+        run(1);
+        done += 1;
+    }
+}
+
+/// Sleep while `state` is [`RunState::Paused`]
+///
+/// Returns the time spent paused as measured by `start` and the state that ended the pause.
+/// An invalid state is treated as [`RunState::Running`].
+/// A fired `watch` ends the pause as [`RunState::Stopped`].
+fn wait_while_paused(
+    state: &AtomicU8,
+    watch: Option<&AtomicU8>,
+    start: &Stopwatch<impl Clock>,
+) -> (Duration, RunState) {
+    let load = || RunState::from_u8(state.load(Ordering::Acquire)).unwrap_or(RunState::Running);
+    let pause_start = start.elapsed();
+    loop {
+        if watch.is_some_and(Watch::fired) {
+            return (start.elapsed() - pause_start, RunState::Stopped);
+        }
+        match load() {
+            RunState::Paused => std::thread::sleep(Duration::from_millis(1)),
+            state => return (start.elapsed() - pause_start, state),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn toggle_phases<G>(
+    clock: &impl Clock,
+    acquire: &mut impl FnMut() -> Option<G>,
+    critical: &mut impl FnMut(&mut G, usize),
+    next_tasks: &mut impl FnMut(Phase, u64) -> usize,
+    work: &mut impl FnMut(usize),
+    scratch: &mut Scratch,
+    duration_limit: Duration,
+    options: &ToggleOptions,
+    watch: Option<&AtomicU8>,
+) -> RunResult {
+    let mut tasks_done: u64 = 0;
+    let mut max_wait = Duration::ZERO;
+    let mut held = options.track_hold_time.then_some(Duration::ZERO);
+    let mut acquisitions: u64 = 0;
+    let mut waits = vec![];
+    let mut wait_stats = RunningStats::new();
+    let mut abandoned: u64 = 0;
+    let mut clamped_phases: u64 = 0;
+    let mut wait_histogram = options.wait_histogram.then(WaitHistogram::new);
+    let mut hold_stats = RunningStats::new();
+    let mut checkpoints = vec![];
+    let checkpoint_interval = options.checkpoint_interval.filter(|&interval| interval > 0);
+    let mut next_checkpoint = checkpoint_interval;
+    let mut phases = vec![];
+    let start = Stopwatch::start(clock, options.time_source);
+    let mut paused = Duration::ZERO;
+    let mut phase = Phase::Locked;
+    // Tasks of the current lock phase still to do under [`ToggleOptions::per_task_lock`]
+    let mut per_task_left: usize = 0;
+    // End of the window of [`ToggleOptions::warmup`] and [`ToggleOptions::trim_tail`] if either is set
+    let window_end = (!options.warmup.is_zero() || !options.trim_tail.is_zero())
+        .then(|| duration_limit.saturating_sub(options.trim_tail));
+    let mut measured_tasks: u64 = 0;
+    let mut acquire_overhead = Duration::ZERO;
+    loop {
+        let mut stopped = watch.is_some_and(Watch::fired);
+        if let Some(state) = &options.run_state {
+            let (paused_for, state) = wait_while_paused(state, watch, &start);
+            paused += paused_for;
+            stopped |= state == RunState::Stopped;
+        }
+        // Paused time does not count toward the limit
+        let limit = duration_limit + paused;
+        let duration = start.elapsed();
+        if stopped || duration > limit {
+            let elapsed = duration.saturating_sub(paused);
+            let (tasks_done, elapsed) = match window_end {
+                Some(window_end) => (
+                    measured_tasks,
+                    elapsed.min(window_end).saturating_sub(options.warmup),
+                ),
+                None => (tasks_done, elapsed),
+            };
+            return RunResult {
+                tasks_done,
+                elapsed,
+                paused,
+                max_wait,
+                held,
+                acquisitions,
+                waits,
+                wait_stats,
+                abandoned,
+                phases,
+                clamped_phases,
+                wait_histogram,
+                hold_stats,
+                checkpoints,
+                acquire_overhead,
+                ..Default::default()
+            };
+        }
+
+        let mut tasks = 1;
+        if per_task_left == 0 {
+            tasks = next_tasks(phase, tasks_done);
+            if let Some(max) = options.max_phase_tasks {
+                if tasks > max {
+                    tasks = max;
+                    clamped_phases += 1;
+                }
+            }
+            if options.per_task_lock && phase == Phase::Locked && tasks > 1 {
+                per_task_left = tasks;
+                tasks = 1;
+            }
+        }
+        let tasks_before = tasks_done;
+        let phase_start = Instant::now();
+        let mut acquired = None;
+        let mut released = None;
+        match phase {
+            Phase::Locked => {
+                // Lock then wait until unlock
+                let wait_start = phase_start;
+                // The critical section is exactly this scope: the guard drops before the unlock phase
+                {
+                    let Some(mut guard) = acquire() else {
+                        abandoned += 1;
+                        per_task_left = 0;
+                        if let Some(epoch) = options.epoch {
+                            phases.push(PhaseRecord {
+                                thread: 0,
+                                phase,
+                                start: phase_start - epoch,
+                                acquired: None,
+                                end: epoch.elapsed(),
+                            });
+                        }
+                        phase = phase.next();
+                        continue;
+                    };
+                    if let Some(epoch) = options.epoch {
+                        acquired = Some(epoch.elapsed());
+                    }
+                    let wait = wait_start.elapsed();
+                    acquisitions += 1;
+                    max_wait = max_wait.max(wait);
+                    wait_stats.push(wait.as_nanos() as f64);
+                    if options.record_waits {
+                        waits.push(wait);
+                    }
+                    if let Some(histogram) = &mut wait_histogram {
+                        histogram.record(wait);
+                    }
+                    if !options.acquire_cost.is_zero() {
+                        let cost_start = Instant::now();
+                        while cost_start.elapsed() < options.acquire_cost {
+                            std::hint::spin_loop();
+                        }
+                        acquire_overhead += cost_start.elapsed();
+                    }
+                    let run = |chunk| {
+                        critical(&mut guard, chunk);
+                        work(chunk);
+                    };
+                    let done = match options.cs_jitter_lambda {
+                        Some(lambda) => {
+                            let hold = duration_until_next_event(lambda)
+                                .max(0.)
+                                .min(duration_limit.as_secs_f64());
+                            run_for(Duration::from_secs_f64(hold), &start, limit, run)
+                        }
+                        None => run_chunked(tasks, &start, limit, run),
+                    };
+                    tasks_done += done as u64;
+                    if let Some(epoch) = options.epoch {
+                        released = Some(epoch.elapsed());
+                    }
+                    let hold = wait_start.elapsed() - wait;
+                    hold_stats.push(hold.as_nanos() as f64);
+                    if let Some(held) = &mut held {
+                        *held += hold;
+                    }
+                }
+            }
+            Phase::Unlocked => {
+                // Unlock then wait until lock
+                let run = |chunk| {
+                    work(chunk);
+                    scratch.touch(chunk);
+                };
+                tasks_done += run_chunked(tasks, &start, limit, run) as u64;
+            }
+        }
+        if let Some(epoch) = options.epoch {
+            phases.push(PhaseRecord {
+                thread: 0,
+                phase,
+                start: phase_start - epoch,
+                acquired,
+                end: released.unwrap_or_else(|| epoch.elapsed()),
+            });
+        }
+        if let Some(window_end) = window_end {
+            let now = start.elapsed().saturating_sub(paused);
+            if options.warmup <= now && now <= window_end {
+                measured_tasks += tasks_done - tasks_before;
+            }
+        }
+        if let (Some(next), Some(interval)) = (&mut next_checkpoint, checkpoint_interval) {
+            if tasks_done >= *next {
+                checkpoints.push((start.elapsed().saturating_sub(paused), tasks_done));
+                *next = (tasks_done / interval + 1) * interval;
+            }
+        }
+        // Stay in the lock phase until each of its tasks had its own acquisition
+        per_task_left = per_task_left.saturating_sub(1);
+        if per_task_left == 0 {
+            phase = phase.next();
+        }
+    }
+}
+
+/// Run [`toggle_lock`] on `threads` threads sharing `lock`, one result per thread in thread order
+///
+/// Every thread tracks the total time it held the lock into [`RunResult::held`]; see [`crate::summary::total_hold_by_thread`].
+pub fn toggle_lock_parallel(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RunResult> {
+    toggle_lock_parallel_with_options(
+        lock,
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        threads,
+        &ToggleOptions {
+            track_hold_time: true,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn toggle_lock_parallel_with_options(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+    options: &ToggleOptions,
+) -> Vec<RunResult> {
+    let mut results = toggle_lock_parallel_instrumented(
+        lock,
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        threads,
+        options,
+    );
+    for (thread, res) in results.iter_mut().enumerate() {
+        for record in &mut res.phases {
+            record.thread = thread;
+        }
+    }
+    results
+}
+
+/// A surge of threads joining a run partway through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepLoad {
+    /// Threads contending from the start
+    pub initial_threads: usize,
+    /// Threads joining at `step_at`
+    pub added_threads: usize,
+    /// Elapsed time at which the added threads join
+    pub step_at: Duration,
+}
+
+/// Same as [`toggle_lock_parallel`] but the thread count jumps partway through
+///
+/// The first [`StepLoad::initial_threads`] results belong to the initial threads and the rest to the added ones, which stop along with the initial threads.
+/// Every phase is recorded into [`RunResult::phases`] on a shared epoch, so [`PhaseRecord::wait`] against [`PhaseRecord::start`] shows the waits before and after the step.
+pub fn toggle_lock_step_load(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    step: &StepLoad,
+) -> Vec<RunResult> {
+    let epoch = Instant::now();
+    let options = ToggleOptions {
+        epoch: Some(epoch),
+        ..Default::default()
+    };
+    let threads = step.initial_threads + step.added_threads;
+    let mut results = parallel(threads, |thread| {
+        let mut duration_limit = duration_limit;
+        if thread >= step.initial_threads {
+            std::thread::sleep(step.step_at.saturating_sub(epoch.elapsed()));
+            duration_limit = duration_limit.saturating_sub(epoch.elapsed());
+        }
+        toggle_lock_with_options(lock, lambda_unlock, lambda_lock, duration_limit, &options)
+    });
+    for (thread, res) in results.iter_mut().enumerate() {
+        for record in &mut res.phases {
+            record.thread = thread;
+        }
+    }
+    results
+}
+
+fn toggle_lock_parallel_instrumented(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+    options: &ToggleOptions,
+) -> Vec<RunResult> {
+    if !options.track_reacquires && options.acquisition_order.is_none() {
+        return parallel(threads, |_| {
+            toggle_lock_with_options(lock, lambda_unlock, lambda_lock, duration_limit, options)
+        });
+    }
+
+    let last_holder = AtomicUsize::new(usize::MAX);
+    parallel(threads, |thread| {
+        let mut consecutive_reacquires = 0;
+        let acquire = || {
+            let guard = lock.lock().unwrap();
+            if options.track_reacquires && last_holder.swap(thread, Ordering::Relaxed) == thread {
+                consecutive_reacquires += 1;
+            }
+            if let Some(order) = &options.acquisition_order {
+                order.lock().unwrap().push(thread);
+            }
+            Some(guard)
+        };
+        let mut res = toggle(
+            acquire,
+            no_critical,
+            constant_rate(lambda_unlock, lambda_lock),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            options,
+        );
+        res.consecutive_reacquires = consecutive_reacquires;
+        res
+    })
+}
+
+pub fn toggle_semaphore_parallel(
+    sem: &Semaphore,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RunResult> {
+    parallel(threads, |_| {
+        toggle_semaphore(sem, lambda_unlock, lambda_lock, duration_limit)
+    })
+}
+
+/// How a thread of [`toggle_lock_strategies`] acquires the lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LockStrategy {
+    /// Block in [`Mutex::lock`]
+    Block,
+    /// Spin on [`Mutex::try_lock`], yielding the time slice every so often
+    Spin,
+    /// Spin on [`Mutex::try_lock`] up to [`HYBRID_SPINS`] times, then block
+    Hybrid,
+}
+
+/// Spins before yielding the time slice for [`LockStrategy::Spin`]
+const SPINS_PER_YIELD: usize = 64;
+/// Spins before blocking for [`LockStrategy::Hybrid`]
+pub const HYBRID_SPINS: usize = 100;
+
+impl LockStrategy {
+    fn lock<'a>(self, lock: &'a Mutex<()>) -> MutexGuard<'a, ()> {
+        let mut spins = 0;
+        loop {
+            match self {
+                LockStrategy::Block => return lock.lock().unwrap(),
+                LockStrategy::Hybrid if spins == HYBRID_SPINS => return lock.lock().unwrap(),
+                LockStrategy::Spin | LockStrategy::Hybrid => (),
+            }
+            match lock.try_lock() {
+                Ok(guard) => return guard,
+                Err(TryLockError::Poisoned(e)) => panic!("{e}"),
+                Err(TryLockError::WouldBlock) => (),
+            }
+            spins += 1;
+            if spins % SPINS_PER_YIELD == 0 {
+                std::thread::yield_now();
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+}
+
+/// Same as [`toggle_lock_parallel`] but with one thread per strategy, each acquiring the shared lock its own way
+///
+/// The results are in the order of `strategies`; see [`summarize_by_strategy`].
+pub fn toggle_lock_strategies(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    strategies: &[LockStrategy],
+) -> Vec<RunResult> {
+    parallel(strategies.len(), |thread| {
+        let strategy = strategies[thread];
+        toggle(
+            || Some(strategy.lock(lock)),
+            no_critical,
+            constant_rate(lambda_unlock, lambda_lock),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            &ToggleOptions::default(),
+        )
+    })
+}
+
+/// Group the results of [`toggle_lock_strategies`] by strategy, in the order of [`LockStrategy`]
+pub fn summarize_by_strategy(
+    strategies: &[LockStrategy],
+    results: &[RunResult],
+) -> Vec<(LockStrategy, ParallelSummary)> {
+    let mut groups = std::collections::BTreeMap::<_, Vec<_>>::new();
+    for (&strategy, res) in strategies.iter().zip(results) {
+        groups.entry(strategy).or_default().push(res.clone());
+    }
+    groups
+        .into_iter()
+        .map(|(strategy, results)| (strategy, summarize(&results)))
+        .collect()
+}
+
+/// Same as [`toggle_lock_parallel`] but runs on the global rayon pool instead of spawning threads
+///
+/// If the pool has fewer than `threads` threads, some runs wait for others to finish instead of contending with them.
+#[cfg(feature = "rayon")]
+pub fn toggle_lock_rayon(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RunResult> {
+    use rayon::prelude::*;
+
+    (0..threads)
+        .into_par_iter()
+        .map(|_| toggle_lock(lock, lambda_unlock, lambda_lock, duration_limit))
+        .collect()
+}
+
+#[cfg(feature = "affinity")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Placement {
+    /// One thread per physical core, leaving SMT siblings idle
+    ///
+    /// Assumes the OS numbers the first logical CPU of every physical core before any sibling, as Linux does on x86.
+    PhysicalOnly,
+    /// Every logical CPU, so threads may share a physical core
+    AllLogical,
+}
+
+#[cfg(feature = "affinity")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlacedRun {
+    pub placement: Placement,
+    /// The logical CPUs threads were pinned to, round-robin
+    pub cores: Vec<usize>,
+    pub results: Vec<RunResult>,
+}
+
+/// Same as [`toggle_lock_parallel`] but each thread is pinned to a CPU chosen by `placement`
+#[cfg(feature = "affinity")]
+pub fn toggle_lock_parallel_smt(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+    placement: Placement,
+) -> PlacedRun {
+    let mut cores = core_affinity::get_core_ids().unwrap_or_default();
+    if placement == Placement::PhysicalOnly {
+        cores.truncate(num_cpus::get_physical());
+    }
+    let results = parallel(threads, |thread| {
+        if !cores.is_empty() {
+            core_affinity::set_for_current(cores[thread % cores.len()]);
+        }
+        toggle_lock(lock, lambda_unlock, lambda_lock, duration_limit)
+    });
+    PlacedRun {
+        placement,
+        cores: cores.iter().map(|core| core.id).collect(),
+        results,
+    }
+}
+
+/// Failure of a parallel run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParallelError {
+    /// A thread of a parallel run panicked
+    ThreadPanicked { thread: usize, message: String },
+}
+
+impl fmt::Display for ParallelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParallelError::ThreadPanicked { thread, message } => {
+                write!(f, "thread {thread} panicked: {message}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ParallelError {}
+
+/// Same as [`toggle_lock_parallel`] but invalid rates and panicking threads are reported instead of panicking
+///
+/// A thread panicking poisons `lock`; the error names the first such thread.
+pub fn try_toggle_lock_parallel(
+    lock: &Mutex<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Result<Vec<RunResult>, Error> {
+    check_lambda(lambda_unlock)?;
+    check_lambda(lambda_lock)?;
+    let results = try_parallel(threads, |_| {
+        toggle_lock(lock, lambda_unlock, lambda_lock, duration_limit)
+    })?;
+    Ok(results)
+}
+
+/// Same as [`parallel`] but a panicking thread is reported instead of propagated
+pub(crate) fn try_parallel(
+    threads: usize,
+    run: impl Fn(usize) -> RunResult + Sync,
+) -> Result<Vec<RunResult>, ParallelError> {
+    let run = &run;
+    std::thread::scope(|s| {
+        let handles = (0..threads)
+            .map(|thread| s.spawn(move || run(thread)))
+            .collect::<Vec<_>>();
+        // Join every thread before looking at the errors since the scope panics over a panicked thread left unjoined
+        let results = handles.into_iter().map(|h| h.join()).collect::<Vec<_>>();
+        results
+            .into_iter()
+            .enumerate()
+            .map(|(thread, res)| {
+                res.map_err(|payload| {
+                    let message = payload
+                        .downcast_ref::<&str>()
+                        .map(|s| s.to_string())
+                        .or_else(|| payload.downcast_ref::<String>().cloned())
+                        .unwrap_or_default();
+                    ParallelError::ThreadPanicked { thread, message }
+                })
+            })
+            .collect()
+    })
+}
+
+/// `run` is given the index of the thread it runs on
+pub(crate) fn parallel(threads: usize, run: impl Fn(usize) -> RunResult + Sync) -> Vec<RunResult> {
+    let run = &run;
+    std::thread::scope(|s| {
+        let handles = (0..threads)
+            .map(|thread| s.spawn(move || run(thread)))
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use crate::{
+        clock::MockClock,
+        report::print_report,
+        stats::{check_slo, percentile},
+    };
+
+    use super::*;
+
+    #[test]
+    fn one_thread_rarely_lock() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every task
+        let lambda_lock = 1.0 / 10000.0; // On average, rarely lock
+        let duration_limit = Duration::from_secs(3);
+        let lock = Arc::new(Mutex::new(()));
+
+        let res = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+
+        print_report(&res);
+    }
+
+    #[test]
+    fn one_thread() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(3);
+        let lock = Arc::new(Mutex::new(()));
+
+        let res = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+
+        print_report(&res);
+    }
+
+    #[test]
+    fn two_threads() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(3);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 2;
+
+        let res = toggle_lock_parallel(&lock, lambda_unlock, lambda_lock, duration_limit, threads);
+
+        for res in res {
+            print_report(&res);
+            println!();
+        }
+    }
+
+    #[test]
+    fn four_threads() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(3);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 4;
+
+        let res = toggle_lock_parallel(&lock, lambda_unlock, lambda_lock, duration_limit, threads);
+
+        for res in res {
+            print_report(&res);
+            println!();
+        }
+    }
+
+    #[test]
+    fn two_threads_duty_cycled() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(1);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 2;
+        let options = ToggleOptions {
+            duty_cycle: Some((Duration::from_millis(100), Duration::from_millis(400))),
+            ..Default::default()
+        };
+
+        let res = toggle_lock_parallel_with_options(
+            &lock,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            &options,
+        );
+
+        for res in res {
+            assert!(res.elapsed < duration_limit / 2);
+            print_report(&res);
+            println!();
+        }
+    }
+
+    #[test]
+    fn one_thread_overhead() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(1);
+        let lock = Arc::new(Mutex::new(()));
+
+        let baseline = uncontended_baseline(lambda_unlock, lambda_lock, duration_limit);
+        let contended = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+
+        let lock_overhead = baseline.tasks_per_sec() / contended.tasks_per_sec();
+        println!("Baseline tasks/sec: {:.02}", baseline.tasks_per_sec());
+        println!("Contended tasks/sec: {:.02}", contended.tasks_per_sec());
+        println!("Lock overhead: {:.03}", lock_overhead);
+    }
+
+    #[test]
+    fn merge_segments() {
+        let a = RunResult {
+            tasks_done: 3,
+            elapsed: Duration::from_secs(1),
+            max_wait: Duration::from_millis(5),
+            held: None,
+            acquisitions: 1,
+            waits: vec![Duration::from_millis(5)],
+            consecutive_reacquires: 0,
+            wait_stats: RunningStats::default(),
+            abandoned: 0,
+            phases: vec![],
+            metadata: None,
+            clamped_phases: 0,
+            wait_histogram: None,
+            hold_stats: RunningStats::default(),
+            checkpoints: vec![(Duration::from_millis(500), 2)],
+            paused: Duration::from_secs(1),
+            watchdog_fired: false,
+            acquire_overhead: Duration::from_micros(3),
+        };
+        let b = RunResult {
+            tasks_done: 4,
+            elapsed: Duration::from_secs(2),
+            max_wait: Duration::from_millis(2),
+            held: Some(Duration::from_millis(500)),
+            acquisitions: 2,
+            waits: vec![Duration::from_millis(1), Duration::from_millis(2)],
+            consecutive_reacquires: 1,
+            wait_stats: RunningStats::default(),
+            abandoned: 0,
+            phases: vec![],
+            metadata: Some(RunMetadata::capture()),
+            clamped_phases: 2,
+            wait_histogram: Some(WaitHistogram::new()),
+            hold_stats: RunningStats::default(),
+            checkpoints: vec![(Duration::from_secs(1), 3)],
+            paused: Duration::ZERO,
+            watchdog_fired: true,
+            acquire_overhead: Duration::from_micros(4),
+        };
+
+        let sum = a.merge(&b);
+        assert_eq!(sum.metadata, b.metadata);
+        assert_eq!(sum.tasks_done, 7);
+        assert_eq!(sum.elapsed, Duration::from_secs(3));
+        assert_eq!(sum.max_wait, Duration::from_millis(5));
+        assert_eq!(sum.held, Some(Duration::from_millis(500)));
+        assert_eq!(sum.acquisitions, 3);
+        assert_eq!(sum.waits.len(), 3);
+        assert_eq!(sum.consecutive_reacquires, 1);
+        assert_eq!(sum.clamped_phases, 2);
+        assert_eq!(sum.paused, Duration::from_secs(1));
+        assert!(sum.watchdog_fired);
+        assert_eq!(sum.acquire_overhead, Duration::from_micros(7));
+        assert_eq!(
+            sum.checkpoints,
+            [(Duration::from_millis(500), 2), (Duration::from_secs(2), 6)]
+        );
+
+        let max = a.merge_with(&b, ElapsedMerge::Max);
+        assert_eq!(max.tasks_done, 7);
+        assert_eq!(max.elapsed, Duration::from_secs(2));
+        assert_eq!(max.max_wait, Duration::from_millis(5));
+    }
+
+    #[test]
+    fn four_threads_two_permits() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(1);
+        let sem = Semaphore::new(2);
+        let threads = 4;
+
+        let res =
+            toggle_semaphore_parallel(&sem, lambda_unlock, lambda_lock, duration_limit, threads);
+
+        for res in res {
+            print_report(&res);
+            println!();
+        }
+        assert_eq!(sem.available_permits(), 2);
+    }
+
+    #[test]
+    fn one_permit_excludes_like_mutex() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(200);
+        let sem = Semaphore::new(1);
+        let options = ToggleOptions {
+            epoch: Some(Instant::now()),
+            ..Default::default()
+        };
+
+        let res = parallel(4, |_| {
+            toggle_semaphore_with_options(
+                &sem,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                &options,
+            )
+        });
+
+        let mut holds = res
+            .iter()
+            .flat_map(|res| &res.phases)
+            .filter_map(|record| Some((record.acquired?, record.end)))
+            .collect::<Vec<_>>();
+        holds.sort();
+        // A single permit is as exclusive as the mutex, so holds never overlap
+        for pair in holds.windows(2) {
+            assert!(pair[0].1 <= pair[1].0);
+        }
+        assert_eq!(
+            holds.len() as u64,
+            res.iter().map(|res| res.acquisitions).sum::<u64>()
+        );
+        assert_eq!(sem.available_permits(), 1);
+    }
+
+    #[test]
+    fn one_thread_ramp_up() {
+        let duration_limit = Duration::from_secs(1);
+        let lock = Arc::new(Mutex::new(()));
+        // Lock more and more often until once every two tasks
+        let lambda_lock_max = 1.0 / 2.0;
+        let lambda_lock_fn = |t: f64| lambda_lock_max * (t / 100_000.0).min(1.0);
 
-use crate::poisson_process::duration_until_next_event;
+        let res = toggle_lock_nhpp(
+            &lock,
+            |_| 1.0 / 2.0,
+            1.0 / 2.0,
+            lambda_lock_fn,
+            lambda_lock_max,
+            duration_limit,
+        );
 
-pub fn toggle_lock(
-    lock: &Mutex<()>,
-    lambda_unlock: f64,
-    lambda_lock: f64,
-    duration_limit: Duration,
-) -> (u64, Duration) {
-    // Each task should be significantly heavier than the control flow overhead
-    const TASK_INNER_LOOP: usize = 128;
+        print_report(&res);
+    }
 
-    let mut tasks_done: u64 = 0;
-    let start = Instant::now();
-    let mut action = 0;
-    let mut rng = rand::thread_rng();
-    loop {
-        let duration = start.elapsed();
-        if duration > duration_limit {
-            return (tasks_done, duration);
+    #[test]
+    fn one_thread_hold_fraction() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 6.0; // On average, lock once every six tasks
+        let duration_limit = Duration::from_secs(1);
+        let lock = Arc::new(Mutex::new(()));
+        let options = ToggleOptions {
+            track_hold_time: true,
+            ..Default::default()
+        };
+
+        let res =
+            toggle_lock_with_options(&lock, lambda_unlock, lambda_lock, duration_limit, &options);
+
+        let hold_fraction = res.hold_fraction().unwrap();
+        println!("Hold fraction: {:.03}", hold_fraction);
+        assert!(0.0 < hold_fraction && hold_fraction < 1.0);
+        assert_eq!(RunResult::default().hold_fraction(), None);
+        let empty = RunResult {
+            held: Some(Duration::ZERO),
+            ..Default::default()
+        };
+        assert_eq!(empty.hold_fraction(), Some(0.0));
+    }
+
+    #[test]
+    fn think_time() {
+        let lambda_unlock = 1.0 / 2.0;
+        let lambda_lock = 1.0 / 2.0;
+        let duration_limit = Duration::from_millis(200);
+        let lock = Mutex::new(());
+        let acquisitions_per_task = |options: &ToggleOptions| {
+            let res = toggle_lock_with_options(
+                &lock,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                options,
+            );
+            res.acquisitions as f64 / res.tasks_done as f64
+        };
+
+        let busy = acquisitions_per_task(&ToggleOptions::default());
+        let thinking = acquisitions_per_task(&ToggleOptions {
+            think_time_lambda: Some(1.0 / 64.0),
+            ..Default::default()
+        });
+        println!("Acquisitions per task: {busy:.04} busy, {thinking:.04} thinking");
+        // About 1/4 against 1/66
+        assert!(thinking * 4. < busy);
+    }
+
+    #[test]
+    fn guard_dropped_before_unlock_phase() {
+        use std::{cell::RefCell, rc::Rc};
+
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        enum Event {
+            Acquire,
+            Release,
+            Work,
         }
+        type Log = Rc<RefCell<Vec<(Event, Instant)>>>;
 
-        match action {
-            0 => {
-                // Lock then wait until unlock
-                let tasks = (duration_until_next_event(lambda_unlock) + 0.5) as usize;
-                tasks_done += tasks as u64;
-                let _guard = lock.lock().unwrap();
-                for _ in 0..tasks {
-                    for _ in 0..TASK_INNER_LOOP {
-                        black_box(duration_until_next_event(lambda_unlock));
-                        black_box(duration_until_next_event(lambda_lock));
-                        black_box(rng.gen::<usize>());
-                    }
-                }
-                action = 1;
+        struct RecordingGuard(Log);
+        impl Drop for RecordingGuard {
+            fn drop(&mut self) {
+                self.0.borrow_mut().push((Event::Release, Instant::now()));
             }
-            1 => {
-                // Unlock then wait until lock
-                let tasks = (duration_until_next_event(lambda_lock) + 0.5) as usize;
-                tasks_done += tasks as u64;
-                for _ in 0..tasks {
-                    for _ in 0..TASK_INNER_LOOP {
-                        black_box(duration_until_next_event(lambda_unlock));
-                        black_box(duration_until_next_event(lambda_lock));
-                        black_box(rng.gen::<usize>());
-                    }
-                }
-                action = 0;
+        }
+        struct RecordingLock(Log);
+        impl Lockable for RecordingLock {
+            type Guard<'a> = RecordingGuard;
+            fn acquire(&self) -> RecordingGuard {
+                self.0.borrow_mut().push((Event::Acquire, Instant::now()));
+                RecordingGuard(Rc::clone(&self.0))
             }
-            _ => unreachable!(),
+        }
+
+        let log: Log = Rc::default();
+        let lock = RecordingLock(Rc::clone(&log));
+        toggle_with_work(
+            || Some(lock.acquire()),
+            no_critical,
+            constant_rate(1.0 / 2.0, 1.0 / 2.0),
+            |_| log.borrow_mut().push((Event::Work, Instant::now())),
+            Duration::from_millis(50),
+            &ToggleOptions::default(),
+        );
+
+        let log = log.borrow();
+        assert!(log.len() > 4);
+        // Every cycle is the locked work inside the guard, then the unlocked work after the release
+        for (i, &(event, _)) in log.iter().enumerate() {
+            let expected = [Event::Acquire, Event::Work, Event::Release, Event::Work][i % 4];
+            assert_eq!(event, expected, "event {i}");
+        }
+        for pair in log.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
         }
     }
-}
 
-pub fn toggle_lock_parallel(
-    lock: &Mutex<()>,
-    lambda_unlock: f64,
-    lambda_lock: f64,
-    duration_limit: Duration,
-    threads: usize,
-) -> Vec<(u64, Duration)> {
-    std::thread::scope(|s| {
-        let handles = (0..threads)
-            .map(|_| s.spawn(|| toggle_lock(lock, lambda_unlock, lambda_lock, duration_limit)))
+    #[test]
+    fn pid_hold_fraction() {
+        let lock = Mutex::new(());
+        let target = 0.75;
+        let run = toggle_lock_pid(&lock, target, Duration::from_secs(1));
+        println!("lambda_lock: {}", run.lambda_lock);
+        // A lock phase of 8 tasks plus an unlock phase of 8/3 tasks
+        // The windows are timed on the wall clock, so only check that the controller moved toward it from the hold fraction of one half it starts at
+        let ideal = 3.0 / 8.0;
+        assert!(PID_LAMBDA_UNLOCK < run.lambda_lock && run.lambda_lock < ideal * 4.);
+
+        let options = ToggleOptions {
+            track_hold_time: true,
+            ..Default::default()
+        };
+        let res = toggle_lock_with_options(
+            &lock,
+            PID_LAMBDA_UNLOCK,
+            run.lambda_lock,
+            Duration::from_millis(200),
+            &options,
+        );
+        let hold_fraction = res.hold_fraction().unwrap();
+        println!("Hold fraction: {hold_fraction:.03}");
+        assert!(0.5 < hold_fraction);
+    }
+
+    #[test]
+    fn step_load() {
+        let lock = Mutex::new(());
+        let step = StepLoad {
+            initial_threads: 1,
+            added_threads: 3,
+            step_at: Duration::from_millis(150),
+        };
+        let res = toggle_lock_step_load(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(300),
+            &step,
+        );
+        assert_eq!(res.len(), 4);
+        for res in &res[1..] {
+            assert!(res.phases[0].start >= step.step_at);
+        }
+
+        let (mut before, mut after) = (vec![], vec![]);
+        for record in res.iter().flat_map(|res| &res.phases) {
+            let Some(wait) = record.wait() else {
+                continue;
+            };
+            if record.start < step.step_at {
+                before.push(wait);
+            } else {
+                after.push(wait);
+            }
+        }
+        let p99_before = percentile(&before, 99.).unwrap();
+        let p99_after = percentile(&after, 99.).unwrap();
+        println!("p99 wait: {p99_before:?} before, {p99_after:?} after");
+        // A lone thread never queues behind another, so the surge can only lengthen the waits on average
+        let mean = |waits: &[Duration]| waits.iter().sum::<Duration>() / waits.len() as u32;
+        assert!(mean(&before) < mean(&after));
+    }
+
+    #[test]
+    fn max_phase_tasks() {
+        let lock = Mutex::new(());
+        let max = 4;
+        let options = ToggleOptions {
+            max_phase_tasks: Some(max),
+            epoch: Some(Instant::now()),
+            ..Default::default()
+        };
+        // An unlock phase lasts 1024 tasks on average
+        let res = toggle_lock_with_options(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 1024.0,
+            Duration::from_millis(200),
+            &options,
+        );
+        assert!(res.clamped_phases > 0);
+        assert!(res.tasks_done <= res.phases.len() as u64 * max as u64);
+
+        let res = toggle_lock(&lock, 1.0 / 2.0, 1.0 / 1024.0, Duration::from_millis(200));
+        assert_eq!(res.clamped_phases, 0);
+    }
+
+    #[test]
+    fn extreme_lambdas_return() {
+        let degenerate = [
+            0.,
+            f64::MIN_POSITIVE,
+            1e-12,
+            1e-3,
+            1.,
+            1e3,
+            1e12,
+            f64::MAX,
+            f64::INFINITY,
+        ];
+        let mut rng = rand::thread_rng();
+        let mut lambdas = vec![];
+        for &lambda_unlock in &degenerate {
+            for &lambda_lock in &degenerate {
+                lambdas.push((lambda_unlock, lambda_lock));
+            }
+        }
+        for _ in 0..32 {
+            let lambda = 10f64.powf(rng.gen_range(-15. ..15.));
+            lambdas.push((lambda, lambda));
+            lambdas.push((lambda, 10f64.powf(rng.gen_range(-15. ..15.))));
+        }
+
+        let duration_limit = Duration::from_millis(2);
+        for (lambda_unlock, lambda_lock) in lambdas {
+            let (tx, rx) = std::sync::mpsc::channel();
+            std::thread::spawn(move || {
+                let lock = Mutex::new(());
+                let res = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+                tx.send(res).unwrap();
+            });
+            let res = rx.recv_timeout(Duration::from_secs(5)).unwrap_or_else(|_| {
+                panic!("hung with lambda_unlock: {lambda_unlock}, lambda_lock: {lambda_lock}")
+            });
+            assert!(res.elapsed >= duration_limit);
+        }
+    }
+
+    #[test]
+    fn progress_ticks() {
+        let lock = Mutex::new(());
+        let mut ticks = vec![];
+        let res = toggle_lock_with_progress(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(300),
+            Duration::from_millis(50),
+            0.3,
+            |progress| ticks.push(*progress),
+        );
+        assert!(ticks.len() >= 3);
+        for pair in ticks.windows(2) {
+            assert!(pair[0].tasks_done <= pair[1].tasks_done);
+            assert!(pair[0].elapsed < pair[1].elapsed);
+        }
+        let last = ticks.last().unwrap();
+        assert_eq!(last.tasks_done, res.tasks_done);
+        assert!(last.smoothed_tasks_per_sec > 0.);
+        println!("Smoothed: {:.0} tasks/sec", last.smoothed_tasks_per_sec);
+    }
+
+    #[test]
+    fn mixed_strategies() {
+        use LockStrategy::*;
+
+        let lock = Mutex::new(());
+        let strategies = [Block, Spin, Hybrid, Block, Spin, Hybrid];
+        let res = toggle_lock_strategies(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(300),
+            &strategies,
+        );
+        assert_eq!(res.len(), strategies.len());
+
+        let groups = summarize_by_strategy(&strategies, &res);
+        let order = groups
+            .iter()
+            .map(|(strategy, _)| *strategy)
             .collect::<Vec<_>>();
-        handles
-            .into_iter()
-            .map(|h| h.join().unwrap())
-            .collect::<Vec<_>>()
-    })
-}
+        assert_eq!(order, [Block, Spin, Hybrid]);
+        for (strategy, summary) in groups {
+            assert_eq!(summary.threads, 2);
+            assert!(summary.total_tasks > 0);
+            println!("{strategy:?}: {:.0} tasks/sec", summary.tasks_per_sec);
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use std::sync::Arc;
+    #[test]
+    fn wait_snapshots() {
+        let lock = Mutex::new(());
+        let snapshots = toggle_lock_snapshots(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(300),
+            Duration::from_millis(100),
+        );
+        assert!(snapshots.len() >= 3);
+        for pair in snapshots.windows(2) {
+            assert!(pair[0].0 < pair[1].0);
+        }
+        for (at, histogram) in &snapshots {
+            assert!(histogram.count() > 0);
+            println!("{at:?}: p99 {:?}", histogram.percentile(99.).unwrap());
+        }
+    }
 
-    use super::*;
+    #[test]
+    fn cs_jitter() {
+        let lock = Mutex::new(());
+        let cs_jitter_lambda = 200.;
+        let options = ToggleOptions {
+            cs_jitter_lambda: Some(cs_jitter_lambda),
+            ..Default::default()
+        };
+        let res = toggle_lock_with_options(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(500),
+            &options,
+        );
+        let mean = res.hold_stats.mean() / 1e9;
+        let stddev = res.hold_stats.stddev() / 1e9;
+        println!(
+            "Holds: {}, mean {mean:.6} s, variance {:.3e} s^2",
+            res.hold_stats.count(),
+            stddev * stddev
+        );
+        // An exponential has a mean and a standard deviation of `1 / lambda`
+        // Preemption by other tests can only stretch holds, so allow more slack above
+        let expected = 1. / cs_jitter_lambda;
+        assert!(expected * 0.6 < mean && mean < expected * 2.);
+        let cv = stddev / mean;
+        assert!(0.5 < cv && cv < 1.5, "coefficient of variation: {cv}");
+
+        // Holds far shorter than a task are not rounded up to one
+        let task_ns = crate::calibration::calibrate_task_ns(Duration::from_millis(50));
+        let options = ToggleOptions {
+            cs_jitter_lambda: Some(1e9),
+            ..Default::default()
+        };
+        let res = toggle_lock_with_options(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(100),
+            &options,
+        );
+        let mean = res.hold_stats.mean();
+        println!("Holds of 1 ns on average: mean {mean:.0} ns against {task_ns:.0} ns per task");
+        assert!(mean < task_ns / 2.);
+    }
 
     #[test]
-    fn one_thread_rarely_lock() {
-        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every task
-        let lambda_lock = 1.0 / 10000.0; // On average, rarely lock
-        let duration_limit = Duration::from_secs(3);
+    fn checkpoints() {
+        let lock = Mutex::new(());
+        let interval = 100;
+        let options = ToggleOptions {
+            checkpoint_interval: Some(interval),
+            ..Default::default()
+        };
+        let res = toggle_lock_with_options(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(200),
+            &options,
+        );
+        assert!(!res.checkpoints.is_empty());
+        let mut next = interval;
+        for &(_, tasks_done) in &res.checkpoints {
+            assert!(tasks_done >= next);
+            next = (tasks_done / interval + 1) * interval;
+        }
+        for pair in res.checkpoints.windows(2) {
+            let (elapsed, tasks) = (pair[1].0 - pair[0].0, pair[1].1 - pair[0].1);
+            assert!(elapsed > Duration::ZERO && tasks > 0);
+        }
+        assert!(res.checkpoints.last().unwrap().1 <= res.tasks_done);
+
+        // A zero interval takes no checkpoints
+        let options = ToggleOptions {
+            checkpoint_interval: Some(0),
+            ..Default::default()
+        };
+        let res = toggle_lock_with_options(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(50),
+            &options,
+        );
+        assert!(res.checkpoints.is_empty());
+        assert!(res.tasks_done > 0);
+    }
+
+    #[test]
+    fn sharded() {
+        let locks = [Mutex::new(()), Mutex::new(())];
+        let res = toggle_lock_sharded(&locks, 1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(200), 4);
+        assert_eq!(res.len(), 4);
+        for res in &res {
+            assert!(res.acquisitions > 0);
+        }
+    }
+
+    #[test]
+    fn checked_parallel() {
+        let lock = Mutex::new(());
+        let res =
+            try_toggle_lock_parallel(&lock, 1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(100), 2);
+        assert_eq!(res.unwrap().len(), 2);
+
+        let res = try_toggle_lock_parallel(&lock, -1., 1.0 / 2.0, Duration::from_millis(100), 2);
+        assert!(matches!(res, Err(Error::Poisson(_))));
+
+        let res = try_parallel(3, |thread| {
+            if thread == 1 {
+                panic!("boom");
+            }
+            RunResult::default()
+        });
+        assert_eq!(
+            res,
+            Err(ParallelError::ThreadPanicked {
+                thread: 1,
+                message: "boom".to_owned()
+            })
+        );
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let lock = Mutex::new(());
+        let state = Arc::new(AtomicU8::new(RunState::Running as u8));
+        let duration_limit = Duration::from_millis(200);
+        let pause = Duration::from_millis(150);
+        let start = Instant::now();
+        let res = std::thread::scope(|s| {
+            let run = s.spawn(|| {
+                toggle_lock_pausable(
+                    &lock,
+                    1.0 / 2.0,
+                    1.0 / 2.0,
+                    duration_limit,
+                    Arc::clone(&state),
+                )
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            state.store(RunState::Paused as u8, Ordering::Release);
+            std::thread::sleep(pause);
+            state.store(RunState::Running as u8, Ordering::Release);
+            run.join().unwrap()
+        });
+        let wall = start.elapsed();
+        println!(
+            "Elapsed {:?}, paused {:?}, wall {wall:?}",
+            res.elapsed, res.paused
+        );
+        assert!(res.paused >= pause / 2);
+        assert!(res.elapsed >= duration_limit);
+        assert!(res.elapsed + res.paused <= wall);
+
+        // Stopping ends the run right away
+        state.store(RunState::Stopped as u8, Ordering::Release);
+        let res = toggle_lock_pausable(&lock, 1.0 / 2.0, 1.0 / 2.0, Duration::from_secs(10), state);
+        assert_eq!(res.tasks_done, 0);
+        assert_eq!(RunState::from_u8(2), Some(RunState::Stopped));
+        assert_eq!(RunState::from_u8(3), None);
+    }
+
+    #[test]
+    fn watchdog() {
+        let lock = Mutex::new(());
+        let duration_limit = Duration::from_millis(50);
+        let options = ToggleOptions {
+            watchdog_factor: Some(2.),
+            ..Default::default()
+        };
+        let res = toggle_lock_with_options(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit, &options);
+        assert!(!res.watchdog_fired);
+        assert!(res.tasks_done > 0);
+
+        // Paused for good, the run would never end on its own
+        let options = ToggleOptions {
+            run_state: Some(Arc::new(AtomicU8::new(RunState::Paused as u8))),
+            ..options
+        };
+        let start = Instant::now();
+        let res = toggle_lock_with_options(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit, &options);
+        let wall = start.elapsed();
+        println!("Watchdog stopped the run after {wall:?}");
+        assert!(res.watchdog_fired);
+        assert!(wall >= duration_limit * 2);
+        assert!(wall < Duration::from_secs(5));
+        // The caller's state is left alone
+        let state = options.run_state.unwrap();
+        assert_eq!(state.load(Ordering::Acquire), RunState::Paused as u8);
+    }
+
+    #[test]
+    fn poisoning_recovery() {
+        let lock = Mutex::new(());
+        let duration_limit = Duration::from_millis(200);
+        let baseline = toggle_lock_poisoning(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit, 2, 0.);
+        assert_eq!((baseline.panics, baseline.recoveries), (0, 0));
+
+        let run = toggle_lock_poisoning(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit, 2, 0.2);
+        println!(
+            "{} panics, {} recoveries: {:.0} against {:.0} tasks/sec",
+            run.panics,
+            run.recoveries,
+            summarize(&run.results).tasks_per_sec,
+            summarize(&baseline.results).tasks_per_sec
+        );
+        assert!(run.panics > 0);
+        assert_eq!(run.recoveries, run.panics);
+        assert!(!lock.is_poisoned());
+    }
+
+    #[test]
+    fn thundering_herd() {
+        let threads = 8;
+        let waits = contended_acquire_latency(&Mutex::new(()), threads);
+        assert_eq!(waits.len(), threads);
+        let mut sorted = waits.clone();
+        sorted.sort();
+        println!(
+            "Herd waits: min {:?}, median {:?}, max {:?}",
+            sorted[0],
+            sorted[threads / 2],
+            sorted[threads - 1]
+        );
+        assert_eq!(contended_acquire_latency(&Mutex::new(()), 0), []);
+    }
+
+    #[test]
+    fn independent_against_shared() {
+        let lambda_unlock = 1.0 / 2.0;
+        let lambda_lock = 1.0 / 2.0;
+        let duration_limit = Duration::from_millis(300);
+        let threads = 2;
+
+        let independent =
+            toggle_lock_independent(lambda_unlock, lambda_lock, duration_limit, threads);
+        let shared = toggle_lock_parallel(
+            &Mutex::new(()),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+        );
+        for (name, res) in [("Independent", independent), ("Shared", shared)] {
+            for (thread, res) in res.iter().enumerate() {
+                println!(
+                    "{name} thread {thread}: {:.0} tasks/sec",
+                    res.tasks_per_sec()
+                );
+            }
+            let summary = summarize(&res);
+            println!("{name}: {:.0} tasks/sec", summary.tasks_per_sec);
+            assert_eq!(summary.threads, threads);
+            assert!(summary.total_tasks > 0);
+        }
+    }
+
+    #[test]
+    fn memory_pressure() {
+        let lock = Mutex::new(());
+        for scratch_bytes in [0, 64 << 10, 16 << 20] {
+            let options = ToggleOptions {
+                scratch_bytes,
+                ..Default::default()
+            };
+            let res = toggle_lock_parallel_with_options(
+                &lock,
+                1.0 / 2.0,
+                1.0 / 2.0,
+                Duration::from_millis(200),
+                2,
+                &options,
+            );
+            let summary = summarize(&res);
+            println!(
+                "Scratch {} KiB: {:.0} tasks/sec",
+                scratch_bytes >> 10,
+                summary.tasks_per_sec
+            );
+            assert!(summary.total_tasks > 0);
+        }
+    }
+
+    #[test]
+    fn acquire_callback() {
+        let lock = Mutex::new(());
+        let mut log = vec![];
+        let res = toggle_lock_on_acquire(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(100),
+            |wait, elapsed| {
+                // Called with the lock held
+                assert!(lock.try_lock().is_err());
+                log.push((wait, elapsed));
+            },
+        );
+        assert_eq!(log.len() as u64, res.acquisitions);
+        for pair in log.windows(2) {
+            assert!(pair[0].1 <= pair[1].1);
+        }
+        assert!(log.iter().all(|&(wait, _)| wait <= res.max_wait));
+    }
+
+    #[test]
+    fn spin_work() {
+        let lock = Mutex::new(());
+        for spin_work in [false, true] {
+            let options = ToggleOptions {
+                spin_work,
+                ..Default::default()
+            };
+            let res = toggle_lock_with_options(
+                &lock,
+                1.0 / 2.0,
+                1.0 / 2.0,
+                Duration::from_millis(200),
+                &options,
+            );
+            println!(
+                "Spin work {spin_work}: {:.0} tasks/sec",
+                res.tasks_per_sec()
+            );
+            assert!(res.tasks_done > 0);
+        }
+    }
+
+    #[test]
+    fn per_task_lock() {
+        let lock = Mutex::new(());
+        let runs = [false, true].map(|per_task_lock| {
+            let options = ToggleOptions {
+                per_task_lock,
+                ..Default::default()
+            };
+            let res = toggle_lock_parallel_with_options(
+                &lock,
+                1.0 / 8.0,
+                1.0 / 8.0,
+                Duration::from_millis(200),
+                2,
+                &options,
+            );
+            let acquisitions = res.iter().map(|res| res.acquisitions).sum::<u64>();
+            let summary = summarize(&res);
+            println!(
+                "Per-task lock {per_task_lock}: {acquisitions} acquisitions, {:.0} tasks/sec",
+                summary.tasks_per_sec
+            );
+            (acquisitions, summary.total_tasks)
+        });
+        let per_acquisition =
+            |(acquisitions, tasks): (u64, u64)| tasks as f64 / acquisitions as f64;
+        // 8 locked and 8 unlocked tasks per cycle on average: 16 tasks per acquisition against 2
+        assert!(per_acquisition(runs[0]) > per_acquisition(runs[1]) * 3.);
+        assert!(per_acquisition(runs[1]) < 4.);
+    }
+
+    #[test]
+    fn mock_clock_ends_run() {
+        // Every task takes exactly a millisecond of mock time
+        let run = || {
+            let clock = MockClock::new();
+            let trace = generate_trace(1.0 / 8.0, 1.0 / 8.0, Duration::ZERO, 3);
+            let mut steps = trace.steps();
+            toggle_with_clock(
+                &clock,
+                || Some(()),
+                no_critical,
+                |_, _| steps.next().unwrap().1,
+                |tasks| clock.advance(Duration::from_millis(tasks as u64)),
+                Duration::from_millis(100),
+                &ToggleOptions::default(),
+            )
+        };
+        let res = run();
+        assert_eq!(res.elapsed, Duration::from_millis(res.tasks_done));
+        assert!(res.elapsed > Duration::from_millis(100));
+        assert!(res.elapsed <= Duration::from_millis(100 + PHASE_CHUNK_TASKS as u64));
+        let again = run();
+        assert_eq!(
+            (again.tasks_done, again.acquisitions, again.elapsed),
+            (res.tasks_done, res.acquisitions, res.elapsed)
+        );
+
+        // Only advancing the mock past the limit ends the run
+        let clock = MockClock::new();
+        let lock = Mutex::new(());
+        let res = std::thread::scope(|s| {
+            let run = s.spawn(|| {
+                toggle_lock_with_clock(&lock, 1.0 / 2.0, 1.0 / 2.0, Duration::from_secs(60), &clock)
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            assert!(!run.is_finished());
+            clock.advance(Duration::from_secs(61));
+            run.join().unwrap()
+        });
+        assert_eq!(res.elapsed, Duration::from_secs(61));
+        assert!(res.tasks_done > 0);
+    }
+
+    #[test]
+    fn steady_state_window() {
+        let lock = Mutex::new(());
+        let duration_limit = Duration::from_millis(300);
+        let full = toggle_lock(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit);
+        let options = ToggleOptions {
+            warmup: Duration::from_millis(100),
+            trim_tail: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let trimmed =
+            toggle_lock_with_options(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit, &options);
+        println!(
+            "Full: {:.0} tasks/sec, steady state: {:.0} tasks/sec",
+            full.tasks_per_sec(),
+            trimmed.tasks_per_sec()
+        );
+        assert_eq!(trimmed.elapsed, Duration::from_millis(100));
+        assert!(0 < trimmed.tasks_done && trimmed.tasks_done < full.tasks_done);
+    }
+
+    #[test]
+    #[should_panic(expected = "warmup and trim_tail must leave a window")]
+    fn empty_steady_state_window() {
+        let options = ToggleOptions {
+            warmup: Duration::from_millis(30),
+            trim_tail: Duration::from_millis(20),
+            ..Default::default()
+        };
+        toggle_lock_with_options(
+            &Mutex::new(()),
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(50),
+            &options,
+        );
+    }
+
+    #[test]
+    fn acquire_cost() {
+        let lock = Mutex::new(());
+        let duration_limit = Duration::from_millis(200);
+        let free = toggle_lock(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit);
+        assert_eq!(free.acquire_overhead, Duration::ZERO);
+
+        let acquire_cost = Duration::from_micros(200);
+        let options = ToggleOptions {
+            acquire_cost,
+            ..Default::default()
+        };
+        let costly =
+            toggle_lock_with_options(&lock, 1.0 / 2.0, 1.0 / 2.0, duration_limit, &options);
+        println!(
+            "Acquire cost {acquire_cost:?}: {:.0} against {:.0} tasks/sec, overhead {:?}",
+            costly.tasks_per_sec(),
+            free.tasks_per_sec(),
+            costly.acquire_overhead
+        );
+        assert!(costly.acquire_overhead >= acquire_cost * costly.acquisitions as u32);
+        assert!(costly.acquire_overhead <= costly.elapsed);
+    }
+
+    #[test]
+    fn custom_work() {
+        let lock = Mutex::new(());
+        let mut calls: u64 = 0;
+        let res = toggle_lock_with_work(
+            &lock,
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(200),
+            || {
+                calls += 1;
+                calls * calls
+            },
+        );
+        assert!(res.tasks_done > 0);
+        assert_eq!(calls, res.tasks_done);
+    }
+
+    #[test]
+    fn two_threads_no_work() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_secs(1);
         let lock = Arc::new(Mutex::new(()));
+        let threads = 2;
+        let options = ToggleOptions {
+            no_work: true,
+            ..Default::default()
+        };
 
-        let (tasks, duration) = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+        let res = toggle_lock_parallel_with_options(
+            &lock,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            &options,
+        );
 
-        println!("Tasks: {tasks}");
-        println!("Duration: {:.02} s", duration.as_secs_f64());
-        let tasks_per_sec = tasks as f64 / duration.as_secs_f64();
-        println!("Tasks/sec: {:.02}", tasks_per_sec);
+        for res in res {
+            assert!(res.acquisitions > 0);
+            println!("Acquisitions: {}", res.acquisitions);
+            println!("Duration: {:.02} s", res.elapsed.as_secs_f64());
+            println!("Acquisitions/sec: {:.02}", res.acquisitions_per_sec());
+            println!();
+        }
     }
 
     #[test]
-    fn one_thread() {
+    fn one_thread_wait_slo() {
         let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
         let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
-        let duration_limit = Duration::from_secs(3);
+        let duration_limit = Duration::from_millis(500);
         let lock = Arc::new(Mutex::new(()));
+        let options = ToggleOptions {
+            record_waits: true,
+            ..Default::default()
+        };
 
-        let (tasks, duration) = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+        let res =
+            toggle_lock_with_options(&lock, lambda_unlock, lambda_lock, duration_limit, &options);
 
-        println!("Tasks: {tasks}");
-        println!("Duration: {:.02} s", duration.as_secs_f64());
-        let tasks_per_sec = tasks as f64 / duration.as_secs_f64();
-        println!("Tasks/sec: {:.02}", tasks_per_sec);
+        // The lock is never contended
+        let budget = Duration::from_millis(10);
+        assert_eq!(res.waits.len() as u64, res.acquisitions);
+        assert!(
+            check_slo(&res.waits, 99., budget),
+            "p99 wait: {:?}",
+            percentile(&res.waits, 99.)
+        );
     }
 
     #[test]
-    fn two_threads() {
+    fn four_threads_reacquires() {
         let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
         let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
-        let duration_limit = Duration::from_secs(3);
+        let duration_limit = Duration::from_secs(1);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 4;
+        let options = ToggleOptions {
+            track_reacquires: true,
+            ..Default::default()
+        };
+
+        let res = toggle_lock_parallel_with_options(
+            &lock,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            &options,
+        );
+
+        for res in res {
+            assert!(res.consecutive_reacquires <= res.acquisitions);
+            assert_eq!(res.wait_stats.count(), res.acquisitions);
+            println!("Acquisitions: {}", res.acquisitions);
+            println!(
+                "Wait: {:.0} ± {:.0} ns",
+                res.wait_stats.mean(),
+                res.wait_stats.stddev()
+            );
+            println!("Consecutive reacquires: {}", res.consecutive_reacquires);
+            println!();
+        }
+    }
+
+    #[cfg(feature = "linux")]
+    #[test]
+    fn two_threads_cpu_time() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(500);
         let lock = Arc::new(Mutex::new(()));
         let threads = 2;
+        let options = ToggleOptions {
+            time_source: TimeSource::ThreadCpu,
+            ..Default::default()
+        };
 
-        let res = toggle_lock_parallel(&lock, lambda_unlock, lambda_lock, duration_limit, threads);
+        let start = Instant::now();
+        let res = toggle_lock_parallel_with_options(
+            &lock,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            &options,
+        );
 
-        for (tasks, duration) in res {
-            println!("Tasks: {tasks}");
-            println!("Duration: {:.02} s", duration.as_secs_f64());
-            let tasks_per_sec = tasks as f64 / duration.as_secs_f64();
-            println!("Tasks/sec: {:.02}", tasks_per_sec);
+        // Blocking on the lock does not count towards CPU time
+        assert!(start.elapsed() >= duration_limit);
+        for res in res {
+            assert!(res.elapsed >= duration_limit);
+            println!("Tasks: {}", res.tasks_done);
+            println!("CPU time: {:.02} s", res.elapsed.as_secs_f64());
+            println!("Tasks/CPU sec: {:.02}", res.tasks_per_sec());
             println!();
         }
     }
 
     #[test]
-    fn four_threads() {
+    fn replay_trace() {
         let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
         let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
-        let duration_limit = Duration::from_secs(3);
+        let duration_limit = Duration::from_millis(500);
+        let trace = generate_trace(lambda_unlock, lambda_lock, duration_limit, 42);
+        let threads = 2;
+
+        assert!(trace.steps().take(128).eq(trace.steps().take(128)));
+
+        let mutex = toggle_lock_replay_parallel(&Mutex::new(()), &trace, threads);
+        let rwlock = toggle_lock_replay_parallel(&RwLock::new(()), &trace, threads);
+        for (name, res) in [("Mutex", mutex), ("RwLock", rwlock)] {
+            println!("{name}");
+            for res in res {
+                print_report(&res);
+                println!();
+            }
+        }
+    }
+
+    #[test]
+    fn giant_lock() {
+        let duration_limit = Duration::from_millis(500);
         let lock = Arc::new(Mutex::new(()));
         let threads = 4;
 
-        let res = toggle_lock_parallel(&lock, lambda_unlock, lambda_lock, duration_limit, threads);
+        for release_fraction in [0.5, 0.2, 0.02] {
+            let (lambda_unlock, lambda_lock) = mostly_held(release_fraction, 1.0 / 50.0);
+
+            let single = toggle_lock(&lock, lambda_unlock, lambda_lock, duration_limit);
+            let res =
+                toggle_lock_parallel(&lock, lambda_unlock, lambda_lock, duration_limit, threads);
+
+            let aggregate = res.iter().map(|res| res.tasks_per_sec()).sum::<f64>();
+            let speedup = aggregate / single.tasks_per_sec();
+            println!("Release fraction: {release_fraction}");
+            println!("Speedup: {speedup:.02}");
+            println!();
+            if release_fraction < 0.05 {
+                // Barely any work can run outside of the lock
+                assert!(speedup < 1.5);
+            }
+        }
+    }
+
+    #[test]
+    fn four_threads_acquisition_order() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(500);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 4;
+        let order = Arc::new(Mutex::new(vec![]));
+        let options = ToggleOptions {
+            acquisition_order: Some(Arc::clone(&order)),
+            ..Default::default()
+        };
+
+        let res = toggle_lock_parallel_with_options(
+            &lock,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            &options,
+        );
+
+        let order = order.lock().unwrap();
+        for (thread, res) in res.iter().enumerate() {
+            let logged = order.iter().filter(|t| **t == thread).count();
+            assert_eq!(logged as u64, res.acquisitions);
+        }
+        let switches = order.windows(2).filter(|w| w[0] != w[1]).count();
+        println!("Acquisitions: {}", order.len());
+        println!("Holder switches: {switches}");
+    }
+
+    #[test]
+    fn one_thread_until_stable() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let max_duration = Duration::from_secs(2);
+        let lock = Arc::new(Mutex::new(()));
+
+        let loose = toggle_lock_until_stable(&lock, lambda_unlock, lambda_lock, 1.0, max_duration);
+        assert!(loose.converged);
+        assert!(loose.result.elapsed < max_duration);
+
+        let strict = toggle_lock_until_stable(&lock, lambda_unlock, lambda_lock, 0.0, max_duration);
+        println!("Converged: {}", strict.converged);
+        print_report(&strict.result);
+    }
+
+    #[cfg(feature = "parking-lot")]
+    #[test]
+    fn rwlock_preference() {
+        // Read-heavy: readers hold the lock most of the time
+        let (reader_pref, writer_pref) = compare_rwlock_preference(
+            1.0 / 16.0,
+            1.0 / 4.0,
+            Duration::from_millis(300),
+            3,
+            1,
+            Duration::from_millis(1),
+        );
+        for (name, run) in [("Readers", &reader_pref), ("Writers", &writer_pref)] {
+            println!(
+                "{name} preferred: reads {:.0}/s, writes {:.0}/s, writer max wait {:?}, starved writes {}",
+                run.readers.tasks_per_sec,
+                run.writers.tasks_per_sec,
+                run.writer_max_wait,
+                run.starved_writes
+            );
+            assert_eq!(run.readers.threads, 3);
+            assert_eq!(run.writers.threads, 1);
+            assert!(run.readers.total_tasks > 0);
+        }
+    }
+
+    #[cfg(feature = "parking-lot")]
+    #[test]
+    fn four_threads_timeout() {
+        let lambda_unlock = 1.0 / 64.0; // On average, unlock once every 64 tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(500);
+        let lock = parking_lot::Mutex::new(());
+        let threads = 4;
+        let acquire_timeout = Duration::from_micros(10);
+
+        let res = parallel(threads, |_| {
+            toggle_lock_timeout(
+                &lock,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                acquire_timeout,
+            )
+        });
 
-        for (tasks, duration) in res {
-            println!("Tasks: {tasks}");
-            println!("Duration: {:.02} s", duration.as_secs_f64());
-            let tasks_per_sec = tasks as f64 / duration.as_secs_f64();
-            println!("Tasks/sec: {:.02}", tasks_per_sec);
+        for res in res {
+            print_report(&res);
+            println!("Abandoned: {}", res.abandoned);
             println!();
         }
     }
+
+    #[cfg(feature = "affinity")]
+    #[test]
+    fn two_threads_smt() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(500);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 2;
+
+        for placement in [Placement::PhysicalOnly, Placement::AllLogical] {
+            let res = toggle_lock_parallel_smt(
+                &lock,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                threads,
+                placement,
+            );
+            assert_eq!(res.placement, placement);
+            assert!(!res.cores.is_empty());
+            println!("{:?} on {:?}", res.placement, res.cores);
+            for res in res.results {
+                print_report(&res);
+                println!();
+            }
+        }
+    }
+
+    #[test]
+    fn two_threads_shared_epoch() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(200);
+        let lock = Arc::new(Mutex::new(()));
+        let threads = 2;
+        let options = ToggleOptions {
+            epoch: Some(Instant::now()),
+            ..Default::default()
+        };
+
+        let res = toggle_lock_parallel_with_options(
+            &lock,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            &options,
+        );
+
+        let mut timeline = res
+            .iter()
+            .flat_map(|res| res.phases.iter().copied())
+            .filter(|record| record.acquired.is_some())
+            .collect::<Vec<_>>();
+        timeline.sort_by_key(|record| record.acquired);
+        // The lock is exclusive so critical sections never overlap on the shared timeline
+        for pair in timeline.windows(2) {
+            assert!(pair[0].end <= pair[1].acquired.unwrap());
+        }
+        for (thread, res) in res.iter().enumerate() {
+            assert!(res.phases.iter().all(|record| record.thread == thread));
+            assert_eq!(
+                res.phases
+                    .iter()
+                    .filter(|record| record.acquired.is_some())
+                    .count() as u64,
+                res.acquisitions
+            );
+        }
+    }
 }
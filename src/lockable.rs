@@ -0,0 +1,49 @@
+use std::sync::{Mutex, MutexGuard, RwLock, RwLockWriteGuard};
+
+use crate::{
+    semaphore::{Semaphore, SemaphorePermit},
+    ticket_lock::{TicketLock, TicketLockGuard},
+};
+
+/// A primitive that grants exclusive access until the returned guard is dropped
+pub trait Lockable {
+    type Guard<'a>
+    where
+        Self: 'a;
+
+    fn acquire(&self) -> Self::Guard<'_>;
+}
+
+impl Lockable for Mutex<()> {
+    type Guard<'a> = MutexGuard<'a, ()>;
+
+    fn acquire(&self) -> Self::Guard<'_> {
+        self.lock().unwrap()
+    }
+}
+
+/// Acquires the write lock
+impl Lockable for RwLock<()> {
+    type Guard<'a> = RwLockWriteGuard<'a, ()>;
+
+    fn acquire(&self) -> Self::Guard<'_> {
+        self.write().unwrap()
+    }
+}
+
+/// Acquires a single permit
+impl Lockable for Semaphore {
+    type Guard<'a> = SemaphorePermit<'a>;
+
+    fn acquire(&self) -> Self::Guard<'_> {
+        Semaphore::acquire(self)
+    }
+}
+
+impl Lockable for TicketLock {
+    type Guard<'a> = TicketLockGuard<'a>;
+
+    fn acquire(&self) -> Self::Guard<'_> {
+        self.lock()
+    }
+}
@@ -0,0 +1,145 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use clap::{Parser, ValueEnum};
+use lock_contention::{
+    config::SimConfig, lock_emulation::RunResult, metadata::RunMetadata, report::format_rate,
+};
+
+/// Emulate lock contentions and examine performance changes
+#[derive(Debug, Parser)]
+struct Cli {
+    /// Rate of unlocking per task while holding the lock
+    #[arg(long)]
+    lambda_unlock: f64,
+    /// Rate of locking per task while not holding the lock
+    #[arg(long)]
+    lambda_lock: f64,
+    #[arg(long, default_value = "3", value_parser = parse_duration_secs)]
+    duration_secs: Duration,
+    #[arg(long, default_value_t = 1)]
+    threads: usize,
+    /// Replay a trace generated from this seed
+    #[arg(long)]
+    seed: Option<u64>,
+    #[arg(long, value_enum, default_value_t = Format::Table)]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
+    Table,
+}
+
+fn parse_duration_secs(s: &str) -> Result<Duration, String> {
+    let secs = s.parse::<f64>().map_err(|e| e.to_string())?;
+    Duration::try_from_secs_f64(secs).map_err(|e| e.to_string())
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let mut config =
+        SimConfig::new(cli.lambda_unlock, cli.lambda_lock, cli.duration_secs).threads(cli.threads);
+    if let Some(seed) = cli.seed {
+        config = config.seed(seed);
+    }
+    let res = config.run(&Mutex::new(()));
+
+    match cli.format {
+        Format::Json => print_json(&res),
+        Format::Csv => print_csv(&res),
+        Format::Table => print_table(&res),
+    }
+}
+
+fn print_json(res: &[RunResult]) {
+    let threads = res
+        .iter()
+        .enumerate()
+        .map(|(thread, res)| {
+            format!(
+                r#"{{"thread":{thread},"tasks_done":{},"elapsed_secs":{},"tasks_per_sec":{},"max_wait_secs":{},"metadata":{}}}"#,
+                res.tasks_done,
+                number(res.elapsed.as_secs_f64()),
+                number(res.tasks_per_sec()),
+                number(res.max_wait.as_secs_f64()),
+                res.metadata.as_ref().map_or("null".to_owned(), metadata_json),
+            )
+        })
+        .collect::<Vec<_>>();
+    println!("[{}]", threads.join(","));
+}
+
+/// JSON has no NaN or infinities, e.g. the tasks/sec of a run of zero elapsed time
+fn number(x: f64) -> String {
+    if x.is_finite() {
+        x.to_string()
+    } else {
+        "null".to_owned()
+    }
+}
+
+fn metadata_json(metadata: &RunMetadata) -> String {
+    fn string(s: &Option<String>) -> String {
+        let Some(s) = s else {
+            return "null".to_owned();
+        };
+        let escaped = s
+            .chars()
+            .map(|c| match c {
+                '"' => "\\\"".to_owned(),
+                '\\' => "\\\\".to_owned(),
+                c if c.is_control() => format!("\\u{:04x}", c as u32),
+                c => c.to_string(),
+            })
+            .collect::<String>();
+        format!(r#""{escaped}""#)
+    }
+
+    let timestamp = metadata
+        .timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        r#"{{"hostname":{},"cpu_model":{},"logical_cpus":{},"unix_timestamp_secs":{}}}"#,
+        string(&metadata.hostname),
+        string(&metadata.cpu_model),
+        metadata
+            .logical_cpus
+            .map_or("null".to_owned(), |cpus| cpus.to_string()),
+        number(timestamp.as_secs_f64()),
+    )
+}
+
+fn print_csv(res: &[RunResult]) {
+    println!("thread,tasks_done,elapsed_secs,tasks_per_sec,max_wait_secs");
+    for (thread, res) in res.iter().enumerate() {
+        println!(
+            "{thread},{},{},{},{}",
+            res.tasks_done,
+            res.elapsed.as_secs_f64(),
+            res.tasks_per_sec(),
+            res.max_wait.as_secs_f64(),
+        );
+    }
+}
+
+fn print_table(res: &[RunResult]) {
+    println!(
+        "{:>6} {:>12} {:>10} {:>14} {:>12}",
+        "thread", "tasks", "duration", "tasks/sec", "max wait"
+    );
+    for (thread, res) in res.iter().enumerate() {
+        println!(
+            "{thread:>6} {:>12} {:>8.02} s {:>14} {:>12}",
+            res.tasks_done,
+            res.elapsed.as_secs_f64(),
+            format_rate(res.tasks_per_sec()),
+            format!("{:?}", res.max_wait),
+        );
+    }
+}
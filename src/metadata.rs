@@ -0,0 +1,109 @@
+//! Provenance of a run, so archived results can be compared across machines
+
+use std::{sync::OnceLock, time::SystemTime};
+
+/// Where and when a run happened
+///
+/// Every lookup is best-effort: a field is `None` when the platform does not expose it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunMetadata {
+    pub hostname: Option<String>,
+    /// The `model name` of the first processor in `/proc/cpuinfo`
+    pub cpu_model: Option<String>,
+    pub logical_cpus: Option<usize>,
+    pub timestamp: SystemTime,
+}
+
+impl RunMetadata {
+    /// The machine stamped with the current time
+    ///
+    /// The machine is looked up on the first call only; later calls clone it and just read the clock,
+    /// so a run can capture its metadata without file reads or syscalls skewing short windows.
+    pub fn capture() -> Self {
+        let machine = machine();
+        Self {
+            hostname: machine.hostname.clone(),
+            cpu_model: machine.cpu_model.clone(),
+            logical_cpus: machine.logical_cpus,
+            timestamp: SystemTime::now(),
+        }
+    }
+}
+
+/// The fields of [`RunMetadata`] that do not change while the process runs
+struct Machine {
+    hostname: Option<String>,
+    cpu_model: Option<String>,
+    logical_cpus: Option<usize>,
+}
+
+fn machine() -> &'static Machine {
+    static MACHINE: OnceLock<Machine> = OnceLock::new();
+    MACHINE.get_or_init(|| Machine {
+        hostname: hostname(),
+        cpu_model: cpu_model(),
+        logical_cpus: std::thread::available_parallelism()
+            .ok()
+            .map(|cpus| cpus.get()),
+    })
+}
+
+fn hostname() -> Option<String> {
+    #[cfg(feature = "linux")]
+    {
+        let mut buf = [0u8; 256];
+        let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+        if ret == 0 {
+            let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+            if let Ok(name) = std::str::from_utf8(&buf[..len]) {
+                return Some(name.to_owned());
+            }
+        }
+    }
+    let name = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .ok()?;
+    let name = name.trim();
+    (!name.is_empty()).then(|| name.to_owned())
+}
+
+fn cpu_model() -> Option<String> {
+    let cpuinfo = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+    parse_cpu_model(&cpuinfo)
+}
+
+fn parse_cpu_model(cpuinfo: &str) -> Option<String> {
+    cpuinfo.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim() == "model name").then(|| value.trim().to_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capture() {
+        let before = SystemTime::now();
+        let metadata = RunMetadata::capture();
+        assert!(metadata.timestamp >= before);
+        assert!(metadata.logical_cpus.unwrap() >= 1);
+        println!("{metadata:?}");
+
+        let again = RunMetadata::capture();
+        assert!(again.timestamp >= metadata.timestamp);
+        assert_eq!(again.hostname, metadata.hostname);
+        assert_eq!(again.cpu_model, metadata.cpu_model);
+    }
+
+    #[test]
+    fn cpuinfo() {
+        let cpuinfo = "processor\t: 0\nvendor_id\t: GenuineIntel\nmodel name\t: Intel(R) Xeon(R) CPU @ 2.20GHz\n\nprocessor\t: 1\nmodel name\t: Intel(R) Xeon(R) CPU @ 2.20GHz\n";
+        assert_eq!(
+            parse_cpu_model(cpuinfo).as_deref(),
+            Some("Intel(R) Xeon(R) CPU @ 2.20GHz")
+        );
+        assert_eq!(parse_cpu_model("processor\t: 0\n"), None);
+    }
+}
@@ -0,0 +1,109 @@
+//! Archive runs into a SQLite database so they can be queried across experiments
+
+use rusqlite::{params, Connection};
+
+use crate::{config::SimConfig, error::Error, summary::ParallelSummary};
+
+/// Insert a row for one run into the `runs` table, creating the table if absent
+///
+/// Non-finite rates, e.g. the NaN tasks/sec of a run of zero elapsed time, are stored as NULL.
+pub fn persist_run(
+    conn: &Connection,
+    config: &SimConfig,
+    summary: &ParallelSummary,
+) -> Result<(), Error> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY,
+            lambda_unlock REAL NOT NULL,
+            lambda_lock REAL NOT NULL,
+            threads INTEGER NOT NULL,
+            duration_secs REAL NOT NULL,
+            seed INTEGER,
+            total_tasks INTEGER NOT NULL,
+            tasks_per_sec REAL,
+            fairness REAL
+        )",
+        (),
+    )?;
+    conn.execute(
+        "INSERT INTO runs (
+            lambda_unlock, lambda_lock, threads, duration_secs, seed, total_tasks, tasks_per_sec, fairness
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        params![
+            config.lambda_unlock,
+            config.lambda_lock,
+            config.threads as i64,
+            config.duration_limit.as_secs_f64(),
+            // SQLite integers are signed; keep the bits of the seed
+            config.seed.map(|seed| seed as i64),
+            summary.total_tasks as i64,
+            finite(summary.tasks_per_sec),
+            finite(summary.fairness),
+        ],
+    )?;
+    Ok(())
+}
+
+fn finite(value: f64) -> Option<f64> {
+    value.is_finite().then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn in_memory() {
+        let conn = Connection::open_in_memory().unwrap();
+        let summary = ParallelSummary {
+            threads: 2,
+            total_tasks: 400,
+            max_elapsed: Duration::from_secs(2),
+            tasks_per_sec: 200.,
+            fairness: 0.8,
+        };
+        let config = SimConfig::new(0.5, 0.25, Duration::from_secs(2)).threads(2);
+        persist_run(&conn, &config, &summary).unwrap();
+        persist_run(&conn, &config.clone().seed(42), &summary).unwrap();
+
+        let rows = conn
+            .prepare("SELECT lambda_lock, threads, tasks_per_sec, seed FROM runs ORDER BY id")
+            .unwrap()
+            .query_map((), |row| {
+                Ok((
+                    row.get::<_, f64>(0)?,
+                    row.get::<_, i64>(1)?,
+                    row.get::<_, f64>(2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(rows, [(0.25, 2, 200., None), (0.25, 2, 200., Some(42))]);
+    }
+
+    #[test]
+    fn zero_elapsed() {
+        let conn = Connection::open_in_memory().unwrap();
+        let summary = ParallelSummary {
+            threads: 1,
+            total_tasks: 0,
+            max_elapsed: Duration::ZERO,
+            tasks_per_sec: f64::NAN,
+            fairness: 1.,
+        };
+        let config = SimConfig::new(0.5, 0.5, Duration::ZERO);
+        persist_run(&conn, &config, &summary).unwrap();
+
+        let tasks_per_sec = conn
+            .query_row("SELECT tasks_per_sec FROM runs", (), |row| {
+                row.get::<_, Option<f64>>(0)
+            })
+            .unwrap();
+        assert_eq!(tasks_per_sec, None);
+    }
+}
@@ -2,41 +2,200 @@
 //!
 //! - blog: <https://preshing.com/20111007/how-to-generate-random-timings-for-a-poisson-process/>
 
-use std::f64::consts::E;
+use std::{f64::consts::E, fmt, time::Duration};
 
 use rand::Rng;
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PoissonError {
+    /// A rate that is not positive, or NaN
+    InvalidLambda(f64),
+}
+
+impl fmt::Display for PoissonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoissonError::InvalidLambda(lambda) => write!(f, "invalid lambda: {lambda}"),
+        }
+    }
+}
+
+impl std::error::Error for PoissonError {}
+
+/// `lambda` itself if it is a valid rate, i.e. positive and not NaN
+pub fn check_lambda(lambda: f64) -> Result<f64, PoissonError> {
+    if lambda > 0. {
+        Ok(lambda)
+    } else {
+        Err(PoissonError::InvalidLambda(lambda))
+    }
+}
+
 pub fn rate(events: f64, time_duration: f64) -> f64 {
     events / time_duration
 }
 
+/// Same as [`rate`] but over a [`Duration`]
+pub fn rate_over(events: f64, duration: Duration) -> f64 {
+    rate(events, duration.as_secs_f64())
+}
+
 pub fn prob_of_one_event_within_next(duration: f64, lambda: f64) -> f64 {
     // CDF of Exponential distribution
     1. - E.powf(-lambda * duration)
 }
 
+/// The duration within which one event happens with probability `prob`
+///
+/// Inverse of [`prob_of_one_event_within_next`]
+pub fn duration_for_prob(prob: f64, lambda: f64) -> f64 {
+    // Quantile of Exponential distribution
+    -(1. - prob).ln() / lambda
+}
+
 pub fn duration_until_next_event(lambda: f64) -> f64 {
-    let mut rng = rand::thread_rng();
+    duration_until_next_event_with(&mut rand::thread_rng(), lambda)
+}
+
+/// Same as [`duration_until_next_event`] but draws from `rng`
+pub fn duration_until_next_event_with<R: Rng + ?Sized>(rng: &mut R, lambda: f64) -> f64 {
     let uniform_rv: f64 = 1. - rng.gen_range(0. ..1.); // (0, 1]
     -(uniform_rv.ln()) / lambda
 }
 
+/// `n` draws of [`duration_until_next_event_with`] from the same `rng`
+pub fn sample_gaps<R: Rng + ?Sized>(rng: &mut R, lambda: f64, n: usize) -> Vec<f64> {
+    (0..n)
+        .map(|_| duration_until_next_event_with(rng, lambda))
+        .collect()
+}
+
+/// Same as [`duration_until_next_event_with`] but in whole nanoseconds
+///
+/// `rate_per_sec` is the number of events per second.
+/// Gaps are rounded to the nearest nanosecond and clamped to `u64::MAX`.
+pub fn duration_until_next_event_nanos<R: Rng + ?Sized>(rng: &mut R, rate_per_sec: f64) -> u64 {
+    secs_to_nanos(duration_until_next_event_with(rng, rate_per_sec))
+}
+
+fn secs_to_nanos(secs: f64) -> u64 {
+    let nanos = (secs * 1e9).round();
+    if nanos >= u64::MAX as f64 {
+        return u64::MAX;
+    }
+    nanos as u64
+}
+
+/// Inter-event gaps obtained by applying the inverse CDF to each uniform
+///
+/// Each uniform must be in `(0, 1]`; `0` maps to an infinite gap.
+pub fn exp_gaps_from_uniforms(uniforms: &[f64], lambda: f64) -> Vec<f64> {
+    uniforms.iter().map(|u| -(u.ln()) / lambda).collect()
+}
+
+/// Duration from `current_time` until the next event of a nonhomogeneous Poisson process with rate `lambda_fn(t)`
+///
+/// Uses thinning: candidate events are drawn at the constant rate `lambda_max` and each one at time `t` is kept with probability `lambda_fn(t) / lambda_max`.
+/// `lambda_max` must be an upper bound of `lambda_fn` from `current_time` onward, otherwise the rate is underrepresented wherever `lambda_fn(t) > lambda_max`.
+pub fn duration_until_next_event_nhpp(
+    lambda_fn: impl Fn(f64) -> f64,
+    lambda_max: f64,
+    current_time: f64,
+) -> f64 {
+    let mut rng = rand::thread_rng();
+    let mut time = current_time;
+    loop {
+        time += duration_until_next_event_with(&mut rng, lambda_max);
+        let accept: f64 = rng.gen_range(0. ..1.);
+        if accept * lambda_max < lambda_fn(time) {
+            return time - current_time;
+        }
+    }
+}
+
+/// Kolmogorov-Smirnov `D` statistic of `samples` against the exponential distribution with rate `lambda`
+///
+/// The largest distance between the empirical CDF of `samples` and [`prob_of_one_event_within_next`].
+/// At significance level `α` the samples are consistent with the distribution if `D` is below roughly `sqrt(-ln(α / 2) / 2) / sqrt(n)`, e.g. `1.63 / sqrt(n)` for `α = 0.01`.
+/// `0` for no samples.
+pub fn ks_test_exponential(samples: &[f64], lambda: f64) -> f64 {
+    let mut sorted = samples.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let n = sorted.len() as f64;
+    sorted
+        .iter()
+        .enumerate()
+        .map(|(i, &x)| {
+            let cdf = prob_of_one_event_within_next(x, lambda);
+            let below = cdf - i as f64 / n;
+            let above = (i + 1) as f64 / n - cdf;
+            below.max(above)
+        })
+        .fold(0., f64::max)
+}
+
+/// Mean time a request waits in the queue of an M/M/1 queue before its service starts
+///
+/// `ρ / (μ - λ)` where `λ = arrival_rate`, `μ = service_rate` and `ρ = λ / μ`.
+/// Returns `None` if `arrival_rate >= service_rate` since the queue then grows without bound.
+///
+/// # Mapping a lock onto the queue
+///
+/// With time measured in tasks:
+///
+/// - each of `n` threads asks for the lock after `1 / lambda_lock` tasks on average, so `arrival_rate ≈ n * lambda_lock`
+/// - the lock is held for `1 / lambda_unlock` tasks on average, so `service_rate = lambda_unlock`
+///
+/// The result is then in tasks; multiply by the duration of a task to compare with a measured wait.
+/// This is only an estimate: a waiting thread stops generating requests, so the real system is a closed queue with `n` customers rather than an open one,
+/// and scheduling and lock handoff costs are ignored.
+pub fn mm1_mean_wait(arrival_rate: f64, service_rate: f64) -> Option<f64> {
+    if arrival_rate >= service_rate {
+        return None;
+    }
+    let utilization = arrival_rate / service_rate;
+    Some(utilization / (service_rate - arrival_rate))
+}
+
+/// The fewest shards that keep each shard below its capacity when `total_rate` is spread evenly across them
+///
+/// `ceil(total_rate * (1 + safety_margin) / per_shard_capacity)`, and at least one shard.
+/// `safety_margin` is the headroom as a fraction of the load, e.g. `0.2` plans for 20% more than `total_rate`,
+/// which inflates the count proportionally before the ceiling.
+/// Verify the estimate with [`crate::lock_emulation::toggle_lock_sharded`].
+pub fn shards_needed(total_rate: f64, per_shard_capacity: f64, safety_margin: f64) -> usize {
+    let shards = (total_rate * (1. + safety_margin) / per_shard_capacity).ceil();
+    (shards as usize).max(1)
+}
+
+/// Probability that the lock is held at time `t` of a single thread alternating between holding and not holding it
+///
+/// The two-state continuous-time Markov chain leaves the held state at rate `lambda_unlock` and the free state at rate `lambda_lock`.
+/// The run starts with a lock phase, so the lock is held at `t = 0`:
+///
+/// `P(t) = π + (1 - π) e^(-(lambda_lock + lambda_unlock) t)` where `π = lambda_lock / (lambda_lock + lambda_unlock)` is the steady-state limit.
+///
+/// `t` is measured in the same unit the rates are per, i.e. tasks for the rates of [`crate::lock_emulation::toggle_lock`].
+pub fn held_probability_at(t: f64, lambda_lock: f64, lambda_unlock: f64) -> f64 {
+    let total = lambda_lock + lambda_unlock;
+    let steady = lambda_lock / total;
+    steady + (1. - steady) * E.powf(-total * t)
+}
+
 #[cfg(test)]
 mod tests {
-    use std::time::Duration;
-
     use super::*;
 
     #[test]
     fn prob_of_next_minute() {
-        let lambda = rate(1., Duration::from_secs(40 * 60).as_secs_f64());
+        let lambda = rate_over(1., Duration::from_secs(40 * 60));
         let prob = prob_of_one_event_within_next(Duration::from_secs(60).as_secs_f64(), lambda);
         assert!((prob - 0.0247).abs() < 0.0001);
     }
 
     #[test]
     fn prob_of_next_10_minutes() {
-        let lambda = rate(1., Duration::from_secs(40 * 60).as_secs_f64());
+        let lambda = rate_over(1., Duration::from_secs(40 * 60));
         let prob =
             prob_of_one_event_within_next(Duration::from_secs(10 * 60).as_secs_f64(), lambda);
         assert!((prob - 0.221).abs() < 0.001);
@@ -44,7 +203,7 @@ mod tests {
 
     #[test]
     fn prob_of_next_40_minutes() {
-        let lambda = rate(1., Duration::from_secs(40 * 60).as_secs_f64());
+        let lambda = rate_over(1., Duration::from_secs(40 * 60));
         let prob =
             prob_of_one_event_within_next(Duration::from_secs(40 * 60).as_secs_f64(), lambda);
         assert!((prob - 0.632).abs() < 0.001);
@@ -52,7 +211,7 @@ mod tests {
 
     #[test]
     fn prob_of_next_64_38_minutes() {
-        let lambda = rate(1., Duration::from_secs(40 * 60).as_secs_f64());
+        let lambda = rate_over(1., Duration::from_secs(40 * 60));
         let prob = prob_of_one_event_within_next(
             Duration::from_secs_f64(64.38 * 60.).as_secs_f64(),
             lambda,
@@ -62,7 +221,7 @@ mod tests {
 
     #[test]
     fn next_event() {
-        let lambda = rate(1., Duration::from_secs(40 * 60).as_secs_f64());
+        let lambda = rate_over(1., Duration::from_secs(40 * 60));
         let events = 128;
         let mut whole_duration = 0.;
         for _ in 0..events {
@@ -72,4 +231,178 @@ mod tests {
         }
         assert!((events as f64 / whole_duration - lambda) < 0.01);
     }
+
+    #[test]
+    fn next_event_nhpp_constant() {
+        let lambda = 2.;
+        let events = 1024;
+        let mut whole_duration = 0.;
+        for _ in 0..events {
+            whole_duration +=
+                duration_until_next_event_nhpp(|_| lambda / 2., lambda, whole_duration);
+        }
+        assert!((events as f64 / whole_duration - lambda / 2.).abs() < 0.1);
+    }
+
+    #[test]
+    fn next_event_nhpp_step() {
+        // No events before time 10
+        let lambda_fn = |t: f64| if t < 10. { 0. } else { 1. };
+        for _ in 0..128 {
+            let duration = duration_until_next_event_nhpp(lambda_fn, 1., 0.);
+            assert!(duration >= 10.);
+        }
+    }
+
+    #[test]
+    fn gaps_from_uniforms() {
+        let uniforms = [1., 0.5, 0.25, E.powi(-1)];
+        let gaps = exp_gaps_from_uniforms(&uniforms, 2.);
+        let expected = [0., 2f64.ln() / 2., 4f64.ln() / 2., 0.5];
+        for (gap, expected) in gaps.iter().zip(expected) {
+            assert!((gap - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn next_event_seeded() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let mut a = StdRng::seed_from_u64(42);
+        let mut b = StdRng::seed_from_u64(42);
+        for _ in 0..128 {
+            assert_eq!(
+                duration_until_next_event_with(&mut a, 0.5),
+                duration_until_next_event_with(&mut b, 0.5)
+            );
+        }
+    }
+
+    #[test]
+    fn batch_of_gaps() {
+        use rand::{rngs::StdRng, SeedableRng};
+
+        let lambda = 1.0 / 4.0;
+        let gaps = sample_gaps(&mut StdRng::seed_from_u64(42), lambda, 100_000);
+        assert_eq!(gaps.len(), 100_000);
+        let mean = gaps.iter().sum::<f64>() / gaps.len() as f64;
+        assert!((mean - 1. / lambda).abs() < 0.05, "{mean}");
+        assert!(sample_gaps(&mut rand::thread_rng(), lambda, 0).is_empty());
+    }
+
+    #[test]
+    fn cdf_quantile_round_trip() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..1024 {
+            let prob: f64 = rng.gen_range(1e-9..1. - 1e-9);
+            let lambda: f64 = rng.gen_range(1e-3..1e3);
+            let duration = duration_for_prob(prob, lambda);
+            let round_trip = prob_of_one_event_within_next(duration, lambda);
+            assert!(
+                (round_trip - prob).abs() < 1e-9,
+                "p: {prob}, lambda: {lambda}"
+            );
+
+            // Beyond ~20 expected events `1 - prob` runs out of precision
+            let duration: f64 = rng.gen_range(1e-3..20.) / lambda;
+            let prob = prob_of_one_event_within_next(duration, lambda);
+            let round_trip = duration_for_prob(prob, lambda);
+            assert!(
+                (round_trip - duration).abs() / duration < 1e-6,
+                "duration: {duration}, lambda: {lambda}"
+            );
+        }
+    }
+
+    #[test]
+    fn next_event_nanos() {
+        assert_eq!(secs_to_nanos(0.), 0);
+        assert_eq!(secs_to_nanos(0.4e-9), 0);
+        assert_eq!(secs_to_nanos(0.6e-9), 1);
+        assert_eq!(secs_to_nanos(1.5e-9), 2);
+        assert_eq!(secs_to_nanos(1.), 1_000_000_000);
+        assert_eq!(secs_to_nanos(f64::INFINITY), u64::MAX);
+        assert_eq!(secs_to_nanos(1e12), u64::MAX);
+
+        // The uniform draw is 0 so the gap is 0
+        let mut rng = rand::rngs::mock::StepRng::new(0, 0);
+        assert_eq!(duration_until_next_event_nanos(&mut rng, 1e9), 0);
+
+        let mut rng = rand::thread_rng();
+        let events = 1024;
+        let rate_per_sec = 1e6;
+        let whole_nanos = (0..events)
+            .map(|_| duration_until_next_event_nanos(&mut rng, rate_per_sec))
+            .sum::<u64>();
+        let mean_nanos = whole_nanos as f64 / events as f64;
+        assert!((mean_nanos - 1e3).abs() < 200.);
+    }
+
+    #[test]
+    fn ks_exponential() {
+        use rand::SeedableRng;
+
+        let n = 2048;
+        let critical_value = 1.63 / (n as f64).sqrt();
+        let lambda = 2.;
+        // Seeded so the test does not fail at the significance level
+        let mut rng = rand::rngs::StdRng::seed_from_u64(42);
+
+        let exponential = (0..n)
+            .map(|_| duration_until_next_event_with(&mut rng, lambda))
+            .collect::<Vec<_>>();
+        let d = ks_test_exponential(&exponential, lambda);
+        assert!(d < critical_value, "D: {d}");
+
+        // Same mean, wrong shape
+        let uniform = (0..n)
+            .map(|_| rng.gen_range(0. ..2. / lambda))
+            .collect::<Vec<_>>();
+        let d = ks_test_exponential(&uniform, lambda);
+        assert!(d > critical_value, "D: {d}");
+
+        assert_eq!(ks_test_exponential(&[], lambda), 0.);
+    }
+
+    #[test]
+    fn mm1() {
+        // ρ = 0.5: W_q = 0.5 / (2 - 1)
+        assert_eq!(mm1_mean_wait(1., 2.), Some(0.5));
+        assert_eq!(mm1_mean_wait(0., 2.), Some(0.));
+        assert_eq!(mm1_mean_wait(2., 2.), None);
+        assert_eq!(mm1_mean_wait(3., 2.), None);
+    }
+
+    #[test]
+    fn shards() {
+        assert_eq!(shards_needed(1000., 250., 0.), 4);
+        assert_eq!(shards_needed(1001., 250., 0.), 5);
+        // 1000 * 1.2 / 250 = 4.8
+        assert_eq!(shards_needed(1000., 250., 0.2), 5);
+        assert_eq!(shards_needed(0., 250., 0.2), 1);
+    }
+
+    #[test]
+    fn lambda_check() {
+        assert_eq!(check_lambda(0.5), Ok(0.5));
+        assert_eq!(check_lambda(f64::INFINITY), Ok(f64::INFINITY));
+        assert_eq!(check_lambda(0.), Err(PoissonError::InvalidLambda(0.)));
+        assert_eq!(check_lambda(-1.), Err(PoissonError::InvalidLambda(-1.)));
+        assert!(check_lambda(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn held_probability() {
+        let (lambda_lock, lambda_unlock) = (1.0 / 2.0, 1.0 / 6.0);
+        assert_eq!(held_probability_at(0., lambda_lock, lambda_unlock), 1.);
+        let steady = lambda_lock / (lambda_lock + lambda_unlock);
+        assert!((held_probability_at(1e3, lambda_lock, lambda_unlock) - steady).abs() < 1e-12);
+        // Decays monotonically toward the limit
+        let mut prev = 1.;
+        for t in 1..32 {
+            let p = held_probability_at(t as f64, lambda_lock, lambda_unlock);
+            assert!(steady < p && p < prev);
+            prev = p;
+        }
+    }
 }
@@ -0,0 +1,199 @@
+//! Producer/consumer contention on a `Mutex` + `Condvar` bounded queue
+
+use std::{
+    collections::VecDeque,
+    sync::{Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{lock_emulation::do_tasks, poisson_process::duration_until_next_event};
+
+#[derive(Debug)]
+pub struct BoundedQueue {
+    items: Mutex<VecDeque<u64>>,
+    capacity: usize,
+    not_full: Condvar,
+    not_empty: Condvar,
+}
+
+impl BoundedQueue {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            items: Mutex::new(VecDeque::with_capacity(capacity)),
+            capacity,
+            not_full: Condvar::new(),
+            not_empty: Condvar::new(),
+        }
+    }
+
+    /// Push `item`, blocking while the queue is full
+    ///
+    /// Returns `None` if `deadline` passed before there was room; otherwise whether it had to block.
+    pub fn push(&self, item: u64, deadline: Instant) -> Option<bool> {
+        let mut items = self.items.lock().unwrap();
+        let blocked = items.len() == self.capacity;
+        while items.len() == self.capacity {
+            let timeout = deadline.checked_duration_since(Instant::now())?;
+            items = self.not_full.wait_timeout(items, timeout).unwrap().0;
+        }
+        items.push_back(item);
+        self.not_empty.notify_one();
+        Some(blocked)
+    }
+
+    /// Pop an item, blocking while the queue is empty
+    ///
+    /// Returns `None` if `deadline` passed before there was an item; otherwise the item and whether it had to block.
+    pub fn pop(&self, deadline: Instant) -> Option<(u64, bool)> {
+        let mut items = self.items.lock().unwrap();
+        let blocked = items.is_empty();
+        loop {
+            if let Some(item) = items.pop_front() {
+                self.not_full.notify_one();
+                return Some((item, blocked));
+            }
+            let timeout = deadline.checked_duration_since(Instant::now())?;
+            items = self.not_empty.wait_timeout(items, timeout).unwrap().0;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Producer,
+    Consumer,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoleResult {
+    pub role: Role,
+    /// Number of items pushed or popped
+    pub operations: u64,
+    /// Number of operations that had to wait on the condvar
+    pub blocked: u64,
+    pub elapsed: Duration,
+}
+
+impl RoleResult {
+    pub fn operations_per_sec(&self) -> f64 {
+        self.operations as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Even-indexed threads produce and odd-indexed threads consume
+///
+/// Between two operations a thread works for a number of tasks sampled from its role's rate.
+pub fn toggle_condvar(
+    queue: &BoundedQueue,
+    lambda_produce: f64,
+    lambda_consume: f64,
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<RoleResult> {
+    let start = Instant::now();
+    let deadline = start + duration_limit;
+    let run = |role| {
+        let lambda = match role {
+            Role::Producer => lambda_produce,
+            Role::Consumer => lambda_consume,
+        };
+        let mut rng = rand::thread_rng();
+        let mut operations = 0;
+        let mut blocked = 0;
+        loop {
+            let tasks = (duration_until_next_event(lambda) + 0.5) as usize;
+            do_tasks(&mut rng, tasks, lambda_produce, lambda_consume);
+            let op_blocked = match role {
+                Role::Producer => queue.push(operations, deadline),
+                Role::Consumer => queue.pop(deadline).map(|(_, blocked)| blocked),
+            };
+            let Some(op_blocked) = op_blocked else {
+                break;
+            };
+            operations += 1;
+            blocked += u64::from(op_blocked);
+            if Instant::now() >= deadline {
+                break;
+            }
+        }
+        RoleResult {
+            role,
+            operations,
+            blocked,
+            elapsed: start.elapsed(),
+        }
+    };
+    std::thread::scope(|s| {
+        let handles = (0..threads)
+            .map(|thread| {
+                let role = match thread % 2 {
+                    0 => Role::Producer,
+                    _ => Role::Consumer,
+                };
+                s.spawn(move || run(role))
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<Vec<_>>()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_times_out() {
+        let queue = BoundedQueue::new(1);
+        let deadline = Instant::now() + Duration::from_millis(10);
+        assert_eq!(queue.pop(deadline), None);
+        assert_eq!(queue.push(1, deadline), Some(false));
+        assert_eq!(queue.push(2, deadline), None);
+        assert_eq!(queue.pop(deadline), Some((1, false)));
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn four_threads_producer_consumer() {
+        let lambda_produce = 1.0 / 2.0; // On average, produce once every two tasks
+        let lambda_consume = 1.0 / 4.0; // On average, consume once every four tasks
+        let duration_limit = Duration::from_millis(500);
+        let queue = BoundedQueue::new(8);
+        let threads = 4;
+
+        let res = toggle_condvar(
+            &queue,
+            lambda_produce,
+            lambda_consume,
+            duration_limit,
+            threads,
+        );
+
+        let count = |role| {
+            res.iter()
+                .filter(|res| res.role == role)
+                .map(|res| res.operations)
+                .sum::<u64>()
+        };
+        assert_eq!(
+            count(Role::Producer),
+            count(Role::Consumer) + queue.len() as u64
+        );
+        for res in res {
+            println!("{:?}", res.role);
+            println!("Operations/sec: {:.02}", res.operations_per_sec());
+            println!("Blocked: {}", res.blocked);
+            println!();
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use std::{io, time::Duration};
+
+use crate::{
+    error::Error,
+    lock_emulation::{Phase, PhaseRecord, RunResult},
+    summary::ParallelSummary,
+};
+
+/// Render a per-second rate with an SI suffix, e.g. `1_500_000.0` as `"1.50 M/s"`
+pub fn format_rate(rate: f64) -> String {
+    const UNITS: [(f64, &str); 3] = [(1e9, "G"), (1e6, "M"), (1e3, "k")];
+    for (scale, unit) in UNITS {
+        if rate.abs() >= scale {
+            return format!("{:.2} {unit}/s", rate / scale);
+        }
+    }
+    format!("{rate:.2} /s")
+}
+
+pub fn print_report(res: &RunResult) {
+    println!("Tasks: {}", res.tasks_done);
+    println!("Duration: {:.02} s", res.elapsed.as_secs_f64());
+    println!("Tasks/sec: {}", format_rate(res.tasks_per_sec()));
+}
+
+/// The tasks/sec between consecutive [`RunResult::checkpoints`] as a line of block characters from `▁` at the lowest rate to `█` at the highest
+///
+/// The first interval starts at the start of the run.
+/// Intervals of zero length are skipped, and a constant rate is drawn at the lowest level.
+pub fn sparkline(checkpoints: &[(Duration, u64)]) -> String {
+    const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+    let mut previous = (Duration::ZERO, 0);
+    let mut rates = vec![];
+    for &(elapsed, tasks_done) in checkpoints {
+        let interval = elapsed.saturating_sub(previous.0);
+        if !interval.is_zero() {
+            rates.push(tasks_done.saturating_sub(previous.1) as f64 / interval.as_secs_f64());
+        }
+        previous = (elapsed, tasks_done);
+    }
+    let min = rates.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = rates.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    rates
+        .iter()
+        .map(|rate| {
+            if max == min {
+                return BLOCKS[0];
+            }
+            let level = (rate - min) / (max - min) * (BLOCKS.len() - 1) as f64;
+            BLOCKS[level.round() as usize]
+        })
+        .collect()
+}
+
+/// Write `phases` as a Chrome trace, loadable by `chrome://tracing` and Perfetto
+///
+/// Each thread gets its own track.
+/// A locked phase is split into `waiting` up to the acquisition and `locked` after it, or is a single `abandoned` event if the lock was never obtained.
+pub fn write_chrome_trace<W: io::Write>(w: &mut W, phases: &[PhaseRecord]) -> Result<(), Error> {
+    fn event<W: io::Write>(
+        w: &mut W,
+        first: &mut bool,
+        name: &str,
+        thread: usize,
+        start: Duration,
+        end: Duration,
+    ) -> io::Result<()> {
+        if !*first {
+            writeln!(w, ",")?;
+        }
+        *first = false;
+        let ts = start.as_secs_f64() * 1e6;
+        let dur = end.saturating_sub(start).as_secs_f64() * 1e6;
+        write!(
+            w,
+            r#"{{"name":"{name}","ph":"X","pid":0,"tid":{thread},"ts":{ts},"dur":{dur}}}"#
+        )
+    }
+
+    writeln!(w, r#"{{"traceEvents":["#)?;
+    let mut first = true;
+    for record in phases {
+        let thread = record.thread;
+        match (record.phase, record.acquired) {
+            (Phase::Unlocked, _) => {
+                event(w, &mut first, "unlocked", thread, record.start, record.end)?
+            }
+            (Phase::Locked, Some(acquired)) => {
+                event(w, &mut first, "waiting", thread, record.start, acquired)?;
+                event(w, &mut first, "locked", thread, acquired, record.end)?;
+            }
+            (Phase::Locked, None) => {
+                event(w, &mut first, "abandoned", thread, record.start, record.end)?
+            }
+        }
+    }
+    writeln!(w)?;
+    writeln!(w, "]}}")?;
+    Ok(())
+}
+
+/// Write `summary` as gauges in the Prometheus text exposition format, e.g. for the textfile collector of the node exporter
+///
+/// Every sample carries `labels`; label values are escaped, label names are written as given.
+pub fn write_prometheus<W: io::Write>(
+    w: &mut W,
+    summary: &ParallelSummary,
+    labels: &[(&str, &str)],
+) -> Result<(), Error> {
+    let labels = if labels.is_empty() {
+        String::new()
+    } else {
+        let labels = labels
+            .iter()
+            .map(|(name, value)| {
+                let value = value
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"")
+                    .replace('\n', "\\n");
+                format!(r#"{name}="{value}""#)
+            })
+            .collect::<Vec<_>>();
+        format!("{{{}}}", labels.join(","))
+    };
+    let gauges = [
+        (
+            "lock_contention_tasks_per_second",
+            "Aggregate tasks per second over all threads",
+            summary.tasks_per_sec,
+        ),
+        (
+            "lock_contention_total_tasks",
+            "Tasks done by all threads",
+            summary.total_tasks as f64,
+        ),
+        (
+            "lock_contention_fairness",
+            "Jain's fairness index of the tasks done by each thread",
+            summary.fairness,
+        ),
+    ];
+    for (name, help, value) in gauges {
+        writeln!(w, "# HELP {name} {help}")?;
+        writeln!(w, "# TYPE {name} gauge")?;
+        writeln!(w, "{name}{labels} {}", prometheus_value(value))?;
+    }
+    Ok(())
+}
+
+/// `value` in the spelling of the Prometheus text format, which has its own for the non-finite values
+fn prometheus_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value == f64::INFINITY {
+        "+Inf".to_owned()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_owned()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_units() {
+        assert_eq!(format_rate(999.), "999.00 /s");
+        assert_eq!(format_rate(1_000.), "1.00 k/s");
+        assert_eq!(format_rate(999_000.), "999.00 k/s");
+        assert_eq!(format_rate(1_000_000.), "1.00 M/s");
+        assert_eq!(format_rate(1_500_000.), "1.50 M/s");
+        assert_eq!(format_rate(1_000_000_000.), "1.00 G/s");
+        assert_eq!(format_rate(2_500_000_000_000.), "2500.00 G/s");
+    }
+
+    #[test]
+    fn prometheus() {
+        let summary = ParallelSummary {
+            threads: 2,
+            total_tasks: 400,
+            max_elapsed: Duration::from_secs(2),
+            tasks_per_sec: 200.,
+            fairness: 0.8,
+        };
+        let mut out = vec![];
+        write_prometheus(&mut out, &summary, &[("host", "a\"b"), ("threads", "2")]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let samples = out
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            samples,
+            [
+                r#"lock_contention_tasks_per_second{host="a\"b",threads="2"} 200"#,
+                r#"lock_contention_total_tasks{host="a\"b",threads="2"} 400"#,
+                r#"lock_contention_fairness{host="a\"b",threads="2"} 0.8"#,
+            ]
+        );
+        for line in out.lines().filter(|line| line.starts_with('#')) {
+            let mut words = line.split(' ');
+            assert_eq!(words.next(), Some("#"));
+            assert!(matches!(words.next(), Some("HELP" | "TYPE")));
+            assert!(words.next().unwrap().starts_with("lock_contention_"));
+        }
+        assert_eq!(out.matches("# TYPE").count(), 3);
+        assert!(out.ends_with('\n'));
+
+        let mut out = vec![];
+        write_prometheus(&mut out, &summary, &[]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\nlock_contention_total_tasks 400\n"));
+
+        // A run of zero elapsed time
+        let summary = ParallelSummary {
+            tasks_per_sec: f64::INFINITY,
+            fairness: f64::NAN,
+            ..summary
+        };
+        let mut out = vec![];
+        write_prometheus(&mut out, &summary, &[]).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("\nlock_contention_tasks_per_second +Inf\n"));
+        assert!(out.contains("\nlock_contention_fairness NaN\n"));
+        assert_eq!(prometheus_value(f64::NEG_INFINITY), "-Inf");
+    }
+
+    #[test]
+    fn sparkline_levels() {
+        let secs = Duration::from_secs;
+        // One second at each rate from 1 to 8 tasks/sec
+        let mut checkpoints = vec![];
+        let mut tasks_done = 0;
+        for rate in 1..=8 {
+            tasks_done += rate;
+            checkpoints.push((secs(rate), tasks_done));
+        }
+        assert_eq!(sparkline(&checkpoints), "▁▂▃▄▅▆▇█");
+
+        let spiky = [
+            (secs(1), 10),
+            (secs(2), 110),
+            (secs(3), 120),
+            (secs(3), 130),
+        ];
+        assert_eq!(sparkline(&spiky), "▁█▁");
+        let steady = [(secs(1), 10), (secs(2), 20)];
+        assert_eq!(sparkline(&steady), "▁▁");
+        assert_eq!(sparkline(&[]), "");
+    }
+
+    #[test]
+    fn chrome_trace() {
+        let micros = Duration::from_micros;
+        let phases = [
+            PhaseRecord {
+                thread: 0,
+                phase: Phase::Unlocked,
+                start: micros(0),
+                acquired: None,
+                end: micros(10),
+            },
+            PhaseRecord {
+                thread: 1,
+                phase: Phase::Locked,
+                start: micros(5),
+                acquired: Some(micros(7)),
+                end: micros(12),
+            },
+        ];
+        let mut out = vec![];
+        write_chrome_trace(&mut out, &phases).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let expected = [
+            r#"{"traceEvents":["#,
+            r#"{"name":"unlocked","ph":"X","pid":0,"tid":0,"ts":0,"dur":10},"#,
+            r#"{"name":"waiting","ph":"X","pid":0,"tid":1,"ts":5,"dur":2},"#,
+            r#"{"name":"locked","ph":"X","pid":0,"tid":1,"ts":7,"dur":5}"#,
+            r#"]}"#,
+        ];
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+
+        let mut out = vec![];
+        write_chrome_trace(&mut out, &[]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"traceEvents\":[\n\n]}\n"
+        );
+    }
+}
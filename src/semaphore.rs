@@ -0,0 +1,78 @@
+use std::sync::{Condvar, Mutex};
+
+/// A counting semaphore allowing up to `permits` simultaneous holders
+#[derive(Debug)]
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a permit is available and take it
+    ///
+    /// The permit is given back when the returned guard is dropped.
+    pub fn acquire(&self) -> SemaphorePermit<'_> {
+        let available = self.available.lock().unwrap();
+        let mut available = self
+            .released
+            .wait_while(available, |available| *available == 0)
+            .unwrap();
+        *available -= 1;
+        SemaphorePermit { semaphore: self }
+    }
+
+    pub fn available_permits(&self) -> usize {
+        *self.available.lock().unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permits_are_returned() {
+        let sem = Semaphore::new(2);
+        {
+            let _a = sem.acquire();
+            let _b = sem.acquire();
+            assert_eq!(sem.available_permits(), 0);
+        }
+        assert_eq!(sem.available_permits(), 2);
+    }
+
+    #[test]
+    fn blocks_until_released() {
+        let sem = Semaphore::new(1);
+        let permit = sem.acquire();
+        std::thread::scope(|s| {
+            let h = s.spawn(|| {
+                let _permit = sem.acquire();
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert!(!h.is_finished());
+            drop(permit);
+            h.join().unwrap();
+        });
+        assert_eq!(sem.available_permits(), 1);
+    }
+}
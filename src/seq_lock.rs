@@ -0,0 +1,375 @@
+//! An optimistic lock for read-mostly data: readers never block, they retry if a writer got in
+
+use std::{
+    cell::{Cell, UnsafeCell},
+    mem::MaybeUninit,
+    ops::{Deref, DerefMut},
+    sync::atomic::{fence, AtomicU8, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use rand::Rng;
+
+use crate::lock_emulation::{constant_rate, do_tasks, no_critical, toggle_with_work, RunResult};
+
+/// Spins before yielding the time slice while waiting for a writer
+const SPINS_PER_YIELD: usize = 64;
+
+/// A sequence lock: writers exclude each other and bump a version, readers validate the version afterwards
+///
+/// The version is odd while a write is in progress.
+/// The value is only ever accessed through per-byte atomics, so readers racing a writer are not a data race;
+/// see [`NoPadding`] for what this asks of `T`.
+#[derive(Debug, Default)]
+pub struct SeqLock<T> {
+    version: AtomicUsize,
+    data: UnsafeCell<T>,
+}
+
+// SAFETY: the value moves along with the lock
+unsafe impl<T: Send> Send for SeqLock<T> {}
+// SAFETY: every thread sharing the lock gets copies of the same value, and all accesses to it are atomic
+unsafe impl<T: Send + Sync> Sync for SeqLock<T> {}
+
+/// `Copy` types of which every byte is initialized, so they can be copied byte by byte
+///
+/// # Safety
+///
+/// The type must have no padding bytes, and neither may any type it is made of.
+pub unsafe trait NoPadding: Copy {}
+
+macro_rules! no_padding {
+    ($($t:ty),*) => {
+        $(
+            // SAFETY: primitives have no padding
+            unsafe impl NoPadding for $t {}
+        )*
+    };
+}
+
+no_padding!(
+    (),
+    bool,
+    char,
+    u8,
+    u16,
+    u32,
+    u64,
+    u128,
+    usize,
+    i8,
+    i16,
+    i32,
+    i64,
+    i128,
+    isize,
+    f32,
+    f64
+);
+
+// SAFETY: array elements are laid out back to back without padding in between
+unsafe impl<T: NoPadding, const N: usize> NoPadding for [T; N] {}
+
+impl<T> SeqLock<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            version: AtomicUsize::new(0),
+            data: UnsafeCell::new(value),
+        }
+    }
+
+    /// Spin until no write is in progress and return the version a read starts from
+    ///
+    /// The waiter yields its time slice every so often so that an oversubscribed machine still makes progress.
+    pub fn read_begin(&self) -> usize {
+        let mut spins = 0;
+        loop {
+            let version = self.version.load(Ordering::Acquire);
+            if version.is_multiple_of(2) {
+                return version;
+            }
+            spins += 1;
+            if spins % SPINS_PER_YIELD == 0 {
+                std::thread::yield_now();
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    /// Whether a write happened since [`SeqLock::read_begin`] returned `version`, so the read must be retried
+    pub fn read_retry(&self, version: usize) -> bool {
+        fence(Ordering::Acquire);
+        self.version.load(Ordering::Relaxed) != version
+    }
+}
+
+impl<T: NoPadding> SeqLock<T> {
+    /// Spin until no other writer holds the lock and take it
+    ///
+    /// The guard works on a copy of the value, which is written back and completes the write when the guard is dropped.
+    pub fn write(&self) -> SeqLockWriteGuard<'_, T> {
+        loop {
+            let version = self.read_begin();
+            if self
+                .version
+                .compare_exchange_weak(version, version + 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                // Keep the data writes after the odd version becomes visible
+                fence(Ordering::Release);
+                // SAFETY: no other writer can store into the value while we hold the lock,
+                // so the copy is of the value last written back as a whole
+                let value = unsafe { self.load_bytes().assume_init() };
+                return SeqLockWriteGuard { lock: self, value };
+            }
+        }
+    }
+
+    /// Copy the value out, retrying until no write overlapped the copy
+    ///
+    /// Returns the value along with the number of retries.
+    /// A copy torn by a concurrent write is discarded without ever being taken as a `T`.
+    pub fn read(&self) -> (T, usize) {
+        let mut retries = 0;
+        loop {
+            let version = self.read_begin();
+            let copy = self.load_bytes();
+            if !self.read_retry(version) {
+                // SAFETY: no write overlapped the copy, so its bytes are those of a `T` written back as a whole,
+                // and `T: NoPadding` has every one of them initialized
+                return (unsafe { copy.assume_init() }, retries);
+            }
+            retries += 1;
+        }
+    }
+
+    /// The bytes of the value, one atomic load each, torn if a write overlaps
+    fn load_bytes(&self) -> MaybeUninit<T> {
+        let mut copy = MaybeUninit::<T>::uninit();
+        let src = self.data.get().cast::<AtomicU8>();
+        let dst = copy.as_mut_ptr().cast::<u8>();
+        for i in 0..size_of::<T>() {
+            // SAFETY: both pointers stay within a `T`, `AtomicU8` has the size and alignment of `u8`,
+            // and the value is only ever accessed through these atomics
+            unsafe { *dst.add(i) = (*src.add(i)).load(Ordering::Relaxed) };
+        }
+        copy
+    }
+
+    /// Store the bytes of `value`, one atomic store each
+    fn store_bytes(&self, value: &T) {
+        let src = (value as *const T).cast::<u8>();
+        let dst = self.data.get().cast::<AtomicU8>();
+        for i in 0..size_of::<T>() {
+            // SAFETY: as in `load_bytes`, and `T: NoPadding` makes every byte of `value` initialized
+            unsafe { (*dst.add(i)).store(*src.add(i), Ordering::Relaxed) };
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SeqLockWriteGuard<'a, T: NoPadding> {
+    lock: &'a SeqLock<T>,
+    value: T,
+}
+
+impl<T: NoPadding> Deref for SeqLockWriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T: NoPadding> DerefMut for SeqLockWriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T: NoPadding> Drop for SeqLockWriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.store_bytes(&self.value);
+        self.lock.version.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SeqLockRun {
+    pub result: RunResult,
+    pub reads: u64,
+    pub writes: u64,
+    /// Read sections redone because a write overlapped them
+    pub reader_retries: u64,
+}
+
+/// Same as [`toggle_lock`](crate::lock_emulation::toggle_lock) but on a [`SeqLock`]
+///
+/// Each lock phase is a write with probability `write_fraction` and a read otherwise.
+/// A write holds the lock for the whole phase.
+/// A read takes no lock: every chunk of its tasks is one read section, done again from scratch whenever a write overlapped it.
+pub fn toggle_seqlock(
+    lock: &SeqLock<()>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    write_fraction: f64,
+) -> SeqLockRun {
+    /// Marks the read phase in progress until dropped
+    struct Reading<'a>(&'a Cell<bool>);
+
+    impl Drop for Reading<'_> {
+        fn drop(&mut self) {
+            self.0.set(false);
+        }
+    }
+
+    let reading = Cell::new(false);
+    let (reads, writes, reader_retries) = (Cell::new(0), Cell::new(0), Cell::new(0));
+    let mut rng = rand::thread_rng();
+    let acquire = || {
+        if rng.gen_bool(write_fraction) {
+            writes.set(writes.get() + 1);
+            return Some((Some(lock.write()), None));
+        }
+        reads.set(reads.get() + 1);
+        reading.set(true);
+        Some((None, Some(Reading(&reading))))
+    };
+    let mut rng = rand::thread_rng();
+    let work = |tasks| {
+        if !reading.get() {
+            do_tasks(&mut rng, tasks, lambda_unlock, lambda_lock);
+            return;
+        }
+        loop {
+            let version = lock.read_begin();
+            do_tasks(&mut rng, tasks, lambda_unlock, lambda_lock);
+            if !lock.read_retry(version) {
+                break;
+            }
+            reader_retries.set(reader_retries.get() + 1);
+        }
+    };
+    let result = toggle_with_work(
+        acquire,
+        no_critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        work,
+        duration_limit,
+        &Default::default(),
+    );
+    SeqLockRun {
+        result,
+        reads: reads.get(),
+        writes: writes.get(),
+        reader_retries: reader_retries.get(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use crate::{lock_emulation::toggle_lock_parallel, summary::summarize};
+
+    use super::*;
+
+    #[test]
+    fn read_retries_after_write() {
+        let lock = SeqLock::new(1);
+        let version = lock.read_begin();
+        assert!(!lock.read_retry(version));
+        *lock.write() = 2;
+        assert!(lock.read_retry(version));
+        assert_eq!(lock.read(), (2, 0));
+    }
+
+    #[test]
+    fn reads_are_never_torn() {
+        // Every write keeps both halves equal, so a torn copy would show up as a mismatch
+        let lock = SeqLock::new([0_u64; 2]);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for i in 1..=10_000 {
+                    *lock.write() = [i; 2];
+                }
+            });
+            for _ in 0..10_000 {
+                let ([a, b], _) = lock.read();
+                assert_eq!(a, b);
+            }
+        });
+        assert_eq!(lock.read().0, [10_000; 2]);
+    }
+
+    #[test]
+    fn writers_exclude_each_other() {
+        let lock = SeqLock::new(0_u64);
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..1000 {
+                        *lock.write() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(lock.read().0, 4000);
+    }
+
+    #[test]
+    fn read_heavy_against_mutex() {
+        let lambda_unlock = 1.0 / 2.0;
+        let lambda_lock = 1.0 / 2.0;
+        let duration_limit = Duration::from_millis(300);
+        let threads = 4;
+
+        let lock = SeqLock::new(());
+        let runs = std::thread::scope(|s| {
+            let handles = (0..threads)
+                .map(|_| {
+                    s.spawn(|| {
+                        toggle_seqlock(&lock, lambda_unlock, lambda_lock, duration_limit, 0.05)
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+        let results = runs
+            .iter()
+            .map(|run| run.result.clone())
+            .collect::<Vec<_>>();
+        let seqlock = summarize(&results);
+        let mutex = summarize(&toggle_lock_parallel(
+            &Mutex::new(()),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+        ));
+        let reads = runs.iter().map(|run| run.reads).sum::<u64>();
+        let writes = runs.iter().map(|run| run.writes).sum::<u64>();
+        let retries = runs.iter().map(|run| run.reader_retries).sum::<u64>();
+        println!(
+            "SeqLock: {:.0} tasks/sec, {reads} reads, {writes} writes, {retries} reader retries",
+            seqlock.tasks_per_sec
+        );
+        println!("Mutex: {:.0} tasks/sec", mutex.tasks_per_sec);
+        assert!(reads > writes);
+        assert!(seqlock.total_tasks > 0);
+    }
+
+    #[test]
+    fn read_only_never_retries() {
+        let lock = SeqLock::new(());
+        let run = toggle_seqlock(&lock, 1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(50), 0.);
+        assert_eq!(run.writes, 0);
+        assert_eq!(run.reader_retries, 0);
+        assert!(run.reads > 0);
+    }
+}
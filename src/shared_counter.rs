@@ -0,0 +1,292 @@
+//! A workload whose critical section updates shared data instead of only holding the lock
+
+use std::{
+    hint::black_box,
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use rand::Rng;
+
+use crate::lock_emulation::{constant_rate, parallel, toggle, RunResult, ToggleOptions};
+
+/// Number of nodes of the [`PointerChain`] walked by [`CounterOptions::cache_miss_loads`]
+///
+/// Large enough to not fit in common L2 caches.
+pub const CHAIN_NODES: usize = 1 << 20;
+
+#[derive(Debug, Clone, Default)]
+pub struct CounterOptions {
+    /// Dependent loads through a randomized [`PointerChain`] per task while holding the lock
+    ///
+    /// Every load is likely a cache miss, so this dials up the memory cost of the critical section.
+    pub cache_miss_loads: usize,
+    /// Put the counter of [`toggle_counter_fresh`] alone in its own cache line with [`CachePadded`]
+    ///
+    /// Unpadded, the counter shares a cache line with the lock word of its mutex,
+    /// so every thread contending for the lock invalidates the line the holder is writing.
+    pub padding: bool,
+}
+
+/// Aligns and pads `T` to a cache line of its own
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[repr(align(64))]
+pub struct CachePadded<T>(pub T);
+
+/// A counter that [`toggle_counter`] can increment
+pub trait Counter {
+    fn increment(&mut self);
+    fn get(&self) -> u64;
+}
+
+impl Counter for u64 {
+    fn increment(&mut self) {
+        *self += 1;
+    }
+
+    fn get(&self) -> u64 {
+        *self
+    }
+}
+
+impl<C: Counter> Counter for CachePadded<C> {
+    fn increment(&mut self) {
+        self.0.increment();
+    }
+
+    fn get(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// A single random cycle over its nodes, defeating the prefetcher when followed
+#[derive(Debug, Clone)]
+pub struct PointerChain {
+    next: Vec<usize>,
+}
+
+impl PointerChain {
+    pub fn new(nodes: usize) -> Self {
+        // Sattolo's algorithm yields a single cycle through every node
+        let mut next = (0..nodes).collect::<Vec<_>>();
+        let mut rng = rand::thread_rng();
+        for i in (1..nodes).rev() {
+            let j = rng.gen_range(0..i);
+            next.swap(i, j);
+        }
+        Self { next }
+    }
+
+    /// Follow `loads` links starting from `node` and return the node reached
+    pub fn walk(&self, mut node: usize, loads: usize) -> usize {
+        for _ in 0..loads {
+            node = self.next[node];
+        }
+        node
+    }
+}
+
+/// Same as [`toggle_lock`](crate::lock_emulation::toggle_lock) but every task of a lock phase increments `counter`
+pub fn toggle_counter<C: Counter>(
+    counter: &Mutex<C>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    options: &CounterOptions,
+) -> RunResult {
+    let chain = build_chain(options);
+    toggle_counter_with_chain(
+        counter,
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        options,
+        chain.as_ref(),
+    )
+}
+
+pub fn toggle_counter_parallel<C: Counter + Send>(
+    counter: &Mutex<C>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+    options: &CounterOptions,
+) -> Vec<RunResult> {
+    let chain = build_chain(options);
+    parallel(threads, |_| {
+        toggle_counter_with_chain(
+            counter,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            options,
+            chain.as_ref(),
+        )
+    })
+}
+
+/// Same as [`toggle_counter_parallel`] on a fresh counter, padded according to [`CounterOptions::padding`]
+///
+/// Returns the final count along with the results.
+pub fn toggle_counter_fresh(
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    threads: usize,
+    options: &CounterOptions,
+) -> (u64, Vec<RunResult>) {
+    fn run<C: Counter + Send>(
+        counter: Mutex<C>,
+        lambda_unlock: f64,
+        lambda_lock: f64,
+        duration_limit: Duration,
+        threads: usize,
+        options: &CounterOptions,
+    ) -> (u64, Vec<RunResult>) {
+        let res = toggle_counter_parallel(
+            &counter,
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            options,
+        );
+        (counter.into_inner().unwrap().get(), res)
+    }
+
+    if options.padding {
+        run(
+            Mutex::new(CachePadded(0)),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            options,
+        )
+    } else {
+        run(
+            Mutex::new(0),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+            options,
+        )
+    }
+}
+
+fn build_chain(options: &CounterOptions) -> Option<PointerChain> {
+    (options.cache_miss_loads > 0).then(|| PointerChain::new(CHAIN_NODES))
+}
+
+fn toggle_counter_with_chain<C: Counter>(
+    counter: &Mutex<C>,
+    lambda_unlock: f64,
+    lambda_lock: f64,
+    duration_limit: Duration,
+    options: &CounterOptions,
+    chain: Option<&PointerChain>,
+) -> RunResult {
+    let mut node = 0;
+    let critical = |counter: &mut MutexGuard<'_, C>, tasks: usize| {
+        for _ in 0..tasks {
+            if let Some(chain) = chain {
+                node = chain.walk(node, options.cache_miss_loads);
+            }
+            counter.increment();
+        }
+        black_box(node);
+    };
+    toggle(
+        || Some(counter.lock().unwrap()),
+        critical,
+        constant_rate(lambda_unlock, lambda_lock),
+        lambda_unlock,
+        lambda_lock,
+        duration_limit,
+        &ToggleOptions::default(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{report::print_report, summary::summarize};
+
+    use super::*;
+
+    #[test]
+    fn chain_is_one_cycle() {
+        let nodes = 1024;
+        let chain = PointerChain::new(nodes);
+        let mut node = 0;
+        for step in 1..=nodes {
+            node = chain.walk(node, 1);
+            if node == 0 {
+                assert_eq!(step, nodes);
+            }
+        }
+        assert_eq!(node, 0);
+    }
+
+    #[test]
+    fn two_threads_cache_misses() {
+        let lambda_unlock = 1.0 / 2.0; // On average, unlock once every two tasks
+        let lambda_lock = 1.0 / 2.0; // On average, lock once every two tasks
+        let duration_limit = Duration::from_millis(500);
+        let threads = 2;
+
+        for cache_miss_loads in [0, 16] {
+            let counter = Mutex::new(0);
+            let options = CounterOptions {
+                cache_miss_loads,
+                ..Default::default()
+            };
+
+            let res = toggle_counter_parallel(
+                &counter,
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                threads,
+                &options,
+            );
+
+            println!("Cache miss loads: {cache_miss_loads}");
+            for res in res {
+                print_report(&res);
+                println!();
+            }
+            assert!(*counter.lock().unwrap() > 0);
+        }
+    }
+
+    #[test]
+    fn padded_against_unpadded() {
+        assert_eq!(std::mem::align_of::<CachePadded<u64>>(), 64);
+        assert_eq!(std::mem::size_of::<CachePadded<u64>>(), 64);
+
+        let lambda_unlock = 1.0 / 2.0;
+        let lambda_lock = 1.0 / 2.0;
+        let duration_limit = Duration::from_millis(300);
+        let threads = 2;
+
+        for padding in [false, true] {
+            let options = CounterOptions {
+                padding,
+                ..Default::default()
+            };
+            let (count, res) = toggle_counter_fresh(
+                lambda_unlock,
+                lambda_lock,
+                duration_limit,
+                threads,
+                &options,
+            );
+            let summary = summarize(&res);
+            println!("Padding {padding}: {:.0} tasks/sec", summary.tasks_per_sec);
+            assert!(count > 0);
+            assert!(count <= summary.total_tasks);
+        }
+    }
+}
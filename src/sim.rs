@@ -0,0 +1,230 @@
+//! Deterministic simulation of lock contention
+//!
+//! This is a model, not a measurement: no OS threads compete for a real lock.
+//! A single thread steps virtual workers on a virtual clock and the lock is only a "held until" timestamp, so the same inputs always give the same [`RunResult`]s.
+//! Use it where tests need exact reproducibility; use [`crate::lock_emulation`] to measure real hardware.
+
+use std::time::Duration;
+
+use crate::{
+    lock_emulation::{Phase, PhaseRecord, RunResult, Trace},
+    stats::RunningStats,
+};
+
+/// Simulate `threads` workers replaying `trace` on one shared lock
+///
+/// - worker `i` follows [`Trace::for_thread`]`(i)`, so `trace.seed` is the master seed
+/// - every task costs `task_cost` of virtual time
+/// - each step advances by one phase the worker with the earliest virtual clock, the lowest index on ties
+/// - a worker entering a lock phase waits until the lock is released by the previous holder
+/// - a worker stops once its virtual clock reaches `trace.duration_limit`
+pub fn simulate(trace: &Trace, threads: usize, task_cost: Duration) -> Vec<RunResult> {
+    simulate_skewed(trace, threads, task_cost, &[])
+}
+
+/// Same as [`simulate`] but the clock of worker `i` is skewed by `skews[i]` from the shared timeline
+///
+/// Worker `i` starts at `skews[i]` on the shared timeline, where the lock lives, and runs for `trace.duration_limit` on its own clock.
+/// [`RunResult::elapsed`] is on the worker's own clock, while [`RunResult::phases`] are on the shared timeline.
+/// Workers beyond the end of `skews` are not skewed, so all-zero or no skews give the same results as [`simulate`].
+pub fn simulate_skewed(
+    trace: &Trace,
+    threads: usize,
+    task_cost: Duration,
+    skews: &[Duration],
+) -> Vec<RunResult> {
+    struct Worker<I> {
+        steps: I,
+        skew: Duration,
+        clock: Duration,
+        result: RunResult,
+    }
+
+    let mut workers = (0..threads)
+        .map(|thread| {
+            let skew = skews.get(thread).copied().unwrap_or_default();
+            Worker {
+                steps: trace.for_thread(thread).steps(),
+                skew,
+                clock: skew,
+                result: RunResult {
+                    held: Some(Duration::ZERO),
+                    wait_stats: RunningStats::new(),
+                    ..Default::default()
+                },
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut lock_held_until = Duration::ZERO;
+
+    while let Some((thread, worker)) = workers
+        .iter_mut()
+        .enumerate()
+        .filter(|(_, worker)| worker.clock < worker.skew.saturating_add(trace.duration_limit))
+        .min_by_key(|(_, worker)| worker.clock)
+    {
+        let (phase, tasks) = worker.steps.next().unwrap();
+        let work = phase_work(task_cost, tasks);
+        let start = worker.clock;
+        let res = &mut worker.result;
+        let acquired = match phase {
+            Phase::Locked => {
+                let acquired = start.max(lock_held_until);
+                let wait = acquired - start;
+                res.acquisitions += 1;
+                res.max_wait = res.max_wait.max(wait);
+                res.wait_stats.push(wait.as_nanos() as f64);
+                let held = res.held.as_mut().unwrap();
+                *held = held.saturating_add(work);
+                lock_held_until = acquired.saturating_add(work);
+                Some(acquired)
+            }
+            Phase::Unlocked => None,
+        };
+        worker.clock = acquired.unwrap_or(start).saturating_add(work);
+        res.tasks_done += tasks as u64;
+        res.phases.push(PhaseRecord {
+            thread,
+            phase,
+            start,
+            acquired,
+            end: worker.clock,
+        });
+    }
+
+    workers
+        .into_iter()
+        .map(|worker| RunResult {
+            elapsed: worker.clock - worker.skew,
+            ..worker.result
+        })
+        .collect()
+}
+
+/// `task_cost * tasks` of virtual time, saturating at [`Duration::MAX`]
+fn phase_work(task_cost: Duration, tasks: usize) -> Duration {
+    let nanos = task_cost.as_nanos().saturating_mul(tasks as u128);
+    u64::try_from(nanos).map_or(Duration::MAX, Duration::from_nanos)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{lock_emulation::generate_trace, summary::jain_fairness};
+
+    use super::*;
+
+    #[test]
+    fn reproducible() {
+        let trace = generate_trace(1.0 / 64.0, 1.0 / 64.0, Duration::from_millis(10), 42);
+        let task_cost = Duration::from_nanos(100);
+        let a = simulate(&trace, 4, task_cost);
+        let b = simulate(&trace, 4, task_cost);
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 4);
+        for res in &a {
+            assert!(res.elapsed >= trace.duration_limit);
+            assert!(res.tasks_done > 0);
+        }
+        assert!(a.iter().any(|res| res.max_wait > Duration::ZERO));
+
+        let other_seed = generate_trace(1.0 / 64.0, 1.0 / 64.0, Duration::from_millis(10), 43);
+        assert_ne!(a, simulate(&other_seed, 4, task_cost));
+    }
+
+    /// Pins the exact outcome of one seed so that any change in behavior of [`Trace::steps`] or [`simulate`] shows up
+    ///
+    /// Only integers are compared, so the assertions do not depend on float formatting.
+    /// The trace is drawn from [`rand_chacha::ChaCha8Rng`], which is the same everywhere,
+    /// but the task counts round the output of `f64::ln`, which comes from the platform's libm and is not guaranteed to be correctly rounded.
+    /// The values were generated on `x86_64-unknown-linux-gnu` with glibc; another libm may flip a rounding and needs its own values.
+    /// After an intended change in behavior, regenerate the expected values with
+    /// `cargo test golden -- --nocapture` and paste the printed lines over `expected`.
+    #[test]
+    fn golden() {
+        let trace = generate_trace(1.0 / 16.0, 1.0 / 32.0, Duration::from_millis(1), 2024);
+        let res = simulate(&trace, 3, Duration::from_nanos(100));
+        let actual = res
+            .iter()
+            .map(|res| {
+                (
+                    res.tasks_done,
+                    res.acquisitions,
+                    res.elapsed.as_nanos() as u64,
+                    res.max_wait.as_nanos() as u64,
+                )
+            })
+            .collect::<Vec<_>>();
+        for line in &actual {
+            println!("{line:?},");
+        }
+        // (tasks_done, acquisitions, elapsed nanos, max_wait nanos) per thread
+        let expected = [
+            (8152, 167, 1004100, 10600),
+            (8201, 169, 1002400, 9600),
+            (8095, 165, 1003300, 7100),
+        ];
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn skews() {
+        let trace = generate_trace(1.0 / 8.0, 1.0 / 8.0, Duration::from_millis(1), 11);
+        let task_cost = Duration::from_nanos(100);
+        let unskewed = simulate(&trace, 3, task_cost);
+        assert_eq!(
+            simulate_skewed(&trace, 3, task_cost, &[Duration::ZERO; 3]),
+            unskewed
+        );
+
+        let skews = [Duration::ZERO, Duration::from_micros(50), Duration::ZERO];
+        let skewed = simulate_skewed(&trace, 3, task_cost, &skews);
+        assert_ne!(skewed, unskewed);
+        assert_eq!(skewed[1].phases[0].start, skews[1]);
+        for res in &skewed {
+            assert!(res.elapsed >= trace.duration_limit);
+        }
+        println!(
+            "Fairness unskewed {:.4}, skewed {:.4}",
+            jain_fairness(&unskewed),
+            jain_fairness(&skewed)
+        );
+    }
+
+    #[test]
+    fn huge_phases() {
+        // Phases of about 1e12 tasks, well past `u32::MAX`
+        let trace = generate_trace(1e-12, 1e-12, Duration::from_millis(1), 5);
+        let res = simulate(&trace, 2, Duration::from_nanos(1));
+        assert!(res[0].tasks_done > u32::MAX as u64);
+        assert_eq!(res[0].elapsed, Duration::from_nanos(res[0].tasks_done));
+        assert!(res[1].elapsed > Duration::from_nanos(res[1].tasks_done));
+
+        // Past even `u64` nanoseconds the clocks saturate instead of panicking
+        let res = simulate(&trace, 2, Duration::from_secs(1_000_000_000));
+        assert_eq!(res[0].elapsed, Duration::MAX);
+        assert_eq!(res[1].elapsed, Duration::MAX);
+    }
+
+    #[test]
+    fn no_overlapping_holds() {
+        let trace = generate_trace(1.0 / 8.0, 1.0 / 8.0, Duration::from_millis(1), 7);
+        let res = simulate(&trace, 3, Duration::from_nanos(50));
+        let mut holds = res
+            .iter()
+            .flat_map(|res| &res.phases)
+            .filter_map(|record| Some((record.acquired?, record.end)))
+            .collect::<Vec<_>>();
+        holds.sort();
+        for pair in holds.windows(2) {
+            assert!(pair[0].1 <= pair[1].0);
+        }
+    }
+
+    #[test]
+    fn one_thread_never_waits() {
+        let trace = generate_trace(1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(1), 0);
+        let res = simulate(&trace, 1, Duration::from_nanos(100));
+        assert_eq!(res[0].max_wait, Duration::ZERO);
+        assert!(res[0].acquisitions > 0);
+    }
+}
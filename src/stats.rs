@@ -0,0 +1,443 @@
+use std::time::Duration;
+
+/// How [`percentile_with`] picks a value between the samples
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PercentileMethod {
+    /// The smallest sample with at least `percentile`% of the samples at or below it
+    #[default]
+    NearestRank,
+    /// Linear interpolation between the two samples around rank `percentile / 100 * (n - 1)`
+    ///
+    /// Same as the default of NumPy and the `PERCENTILE.INC` of spreadsheets.
+    Linear,
+}
+
+/// Nearest-rank percentile of `samples`
+///
+/// `percentile` is in `[0, 100]`.
+/// Returns `None` if `samples` is empty.
+pub fn percentile(samples: &[Duration], percentile: f64) -> Option<Duration> {
+    percentile_with(samples, percentile, PercentileMethod::NearestRank)
+}
+
+/// Same as [`percentile`] but by `method`
+pub fn percentile_with(
+    samples: &[Duration],
+    percentile: f64,
+    method: PercentileMethod,
+) -> Option<Duration> {
+    if samples.is_empty() {
+        return None;
+    }
+    let mut sorted = samples.to_vec();
+    sorted.sort_unstable();
+    match method {
+        PercentileMethod::NearestRank => {
+            let rank = (percentile / 100. * sorted.len() as f64).ceil() as usize;
+            Some(sorted[rank.clamp(1, sorted.len()) - 1])
+        }
+        PercentileMethod::Linear => {
+            let rank = (percentile / 100. * (sorted.len() - 1) as f64)
+                .clamp(0., (sorted.len() - 1) as f64);
+            let (below, above) = (sorted[rank.floor() as usize], sorted[rank.ceil() as usize]);
+            Some(below + (above - below).mul_f64(rank.fract()))
+        }
+    }
+}
+
+/// Whether the `percentile` of `samples` is within `budget`
+///
+/// An empty sample set trivially meets the budget.
+pub fn check_slo(samples: &[Duration], percentile: f64, budget: Duration) -> bool {
+    match self::percentile(samples, percentile) {
+        Some(value) => value <= budget,
+        None => true,
+    }
+}
+
+/// Streaming mean and variance by Welford's algorithm
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RunningStats {
+    count: u64,
+    mean: f64,
+    m2: f64,
+}
+
+impl RunningStats {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Combine the statistics of two disjoint sample sets
+    pub fn merge(&self, other: &RunningStats) -> RunningStats {
+        let count = self.count + other.count;
+        if count == 0 {
+            return RunningStats::default();
+        }
+        let delta = other.mean - self.mean;
+        let weight = other.count as f64 / count as f64;
+        RunningStats {
+            count,
+            mean: self.mean + delta * weight,
+            m2: self.m2 + other.m2 + delta * delta * self.count as f64 * weight,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// `0.0` if no samples were pushed
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    /// Sample variance; `0.0` with fewer than two samples
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            return 0.;
+        }
+        self.m2 / (self.count - 1) as f64
+    }
+
+    pub fn stddev(&self) -> f64 {
+        self.variance().sqrt()
+    }
+}
+
+/// Histogram of wait times in power-of-two nanosecond buckets
+///
+/// Bucket `i` counts waits in `[2^(i-1), 2^i)` ns, bucket `0` the zero waits, so its memory is fixed regardless of how many waits are recorded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WaitHistogram {
+    buckets: [u64; 65],
+}
+
+impl Default for WaitHistogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WaitHistogram {
+    pub fn new() -> Self {
+        Self { buckets: [0; 65] }
+    }
+
+    pub fn record(&mut self, wait: Duration) {
+        let nanos = u64::try_from(wait.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.buckets[bucket] += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Combine the counts of two histograms
+    pub fn merge(&self, other: &WaitHistogram) -> WaitHistogram {
+        let mut buckets = self.buckets;
+        for (a, b) in buckets.iter_mut().zip(other.buckets) {
+            *a += b;
+        }
+        WaitHistogram { buckets }
+    }
+
+    /// Nearest-rank percentile, rounded up to the exclusive upper bound of its bucket
+    ///
+    /// `percentile` is in `[0, 100]`.
+    /// Returns `None` if nothing was recorded.
+    pub fn percentile(&self, percentile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((percentile / 100. * count as f64).ceil() as u64).clamp(1, count);
+        let mut seen = 0;
+        for (bucket, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                let upper = 1u64.checked_shl(bucket as u32).unwrap_or(u64::MAX);
+                return Some(Duration::from_nanos(upper));
+            }
+        }
+        unreachable!()
+    }
+}
+
+/// Exponentially-weighted moving average of a throughput
+///
+/// Each update moves the estimate `alpha` of the way toward the rate of the update; the first update sets it outright.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EwmaThroughput {
+    alpha: f64,
+    value: Option<f64>,
+}
+
+impl EwmaThroughput {
+    /// `alpha` in `(0, 1]`; larger values react faster and smooth less
+    pub fn new(alpha: f64) -> Self {
+        assert!(0. < alpha && alpha <= 1., "alpha must be in (0, 1]");
+        Self { alpha, value: None }
+    }
+
+    pub fn update(&mut self, tasks_delta: u64, time_delta: Duration) {
+        let rate = tasks_delta as f64 / time_delta.as_secs_f64();
+        if !rate.is_finite() {
+            return;
+        }
+        self.value = Some(match self.value {
+            Some(value) => value + self.alpha * (rate - value),
+            None => rate,
+        });
+    }
+
+    /// Smoothed tasks/sec; `0.0` before the first update
+    pub fn current(&self) -> f64 {
+        self.value.unwrap_or(0.)
+    }
+}
+
+/// The smallest difference in mean throughput between two configs that `n_runs` runs of each would detect
+///
+/// Standard power analysis of a two-sided two-sample z-test with equal sample sizes:
+///
+/// `δ = (z(1 - alpha / 2) + z(power)) * within_config_stddev * sqrt(2 / n_runs)`
+///
+/// where `z` is the quantile function of the standard normal and `within_config_stddev` the run-to-run standard deviation of one config, in the unit of the result.
+/// The normal approximation is optimistic for a handful of runs, where a t-test needs a somewhat larger difference.
+/// Infinite for `n_runs == 0`.
+///
+/// # Panics
+///
+/// If `alpha` or `power` is not in `(0, 1)`.
+pub fn min_detectable_diff(
+    n_runs: usize,
+    within_config_stddev: f64,
+    alpha: f64,
+    power: f64,
+) -> f64 {
+    assert!(0. < alpha && alpha < 1., "alpha must be in (0, 1)");
+    assert!(0. < power && power < 1., "power must be in (0, 1)");
+    let z = normal_quantile(1. - alpha / 2.) + normal_quantile(power);
+    z * within_config_stddev * (2. / n_runs as f64).sqrt()
+}
+
+/// Quantile function of the standard normal distribution for `p` in `(0, 1)`
+///
+/// Acklam's rational approximation, with a relative error under `1.2e-9`.
+fn normal_quantile(p: f64) -> f64 {
+    const A: [f64; 6] = [
+        -3.969683028665376e+01,
+        2.209460984245205e+02,
+        -2.759285104469687e+02,
+        1.38357751867269e+02,
+        -3.066479806614716e+01,
+        2.506628277459239e+00,
+    ];
+    const B: [f64; 5] = [
+        -5.447609879822406e+01,
+        1.615858368580409e+02,
+        -1.556989798598866e+02,
+        6.680131188771972e+01,
+        -1.328068155288572e+01,
+    ];
+    const C: [f64; 6] = [
+        -7.784894002430293e-03,
+        -3.223964580411365e-01,
+        -2.400758277161838e+00,
+        -2.549732539343734e+00,
+        4.374664141464968e+00,
+        2.938163982698783e+00,
+    ];
+    const D: [f64; 4] = [
+        7.784695709041462e-03,
+        3.224671290700398e-01,
+        2.445134137142996e+00,
+        3.754408661907416e+00,
+    ];
+    const P_LOW: f64 = 0.02425;
+
+    let tail = |q: f64| {
+        (((((C[0] * q + C[1]) * q + C[2]) * q + C[3]) * q + C[4]) * q + C[5])
+            / ((((D[0] * q + D[1]) * q + D[2]) * q + D[3]) * q + 1.)
+    };
+    if p < P_LOW {
+        return tail((-2. * p.ln()).sqrt());
+    }
+    if p > 1. - P_LOW {
+        return -tail((-2. * (1. - p).ln()).sqrt());
+    }
+    let q = p - 0.5;
+    let r = q * q;
+    (((((A[0] * r + A[1]) * r + A[2]) * r + A[3]) * r + A[4]) * r + A[5]) * q
+        / (((((B[0] * r + B[1]) * r + B[2]) * r + B[3]) * r + B[4]) * r + 1.)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nearest_rank() {
+        let samples = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&samples, 50.), Some(Duration::from_millis(50)));
+        assert_eq!(percentile(&samples, 99.), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&samples, 100.), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&samples, 0.), Some(Duration::from_millis(1)));
+        assert_eq!(percentile(&[], 50.), None);
+    }
+
+    #[test]
+    fn linear_against_nearest_rank() {
+        let millis = Duration::from_millis;
+        let samples = [millis(10), millis(20), millis(30), millis(40)];
+        let nearest = |p| percentile_with(&samples, p, PercentileMethod::NearestRank);
+        let linear = |p| percentile_with(&samples, p, PercentileMethod::Linear);
+        assert_eq!(nearest(50.), Some(millis(20)));
+        assert_eq!(linear(50.), Some(millis(25)));
+        assert_eq!(nearest(90.), Some(millis(40)));
+        assert_eq!(linear(90.), Some(millis(37)));
+        assert_eq!(linear(0.), Some(millis(10)));
+        assert_eq!(linear(100.), Some(millis(40)));
+        assert_eq!(linear(200.), Some(millis(40)));
+        assert_eq!(
+            percentile_with(&[millis(7)], 50., PercentileMethod::Linear),
+            Some(millis(7))
+        );
+        assert_eq!(percentile_with(&[], 50., PercentileMethod::Linear), None);
+
+        // Evenly spread samples: the rank definitions differ by under one sample
+        let samples = (0..100_000).map(Duration::from_micros).collect::<Vec<_>>();
+        for p in [1., 50., 99., 99.9] {
+            let nearest = percentile_with(&samples, p, PercentileMethod::NearestRank).unwrap();
+            let linear = percentile_with(&samples, p, PercentileMethod::Linear).unwrap();
+            let diff = nearest.abs_diff(linear);
+            assert!(
+                diff <= Duration::from_micros(1),
+                "p{p}: {nearest:?} against {linear:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn slo() {
+        let samples = (1..=100).map(Duration::from_micros).collect::<Vec<_>>();
+        let budget = Duration::from_micros(99);
+        assert!(
+            check_slo(&samples, 99., budget),
+            "p99: {:?}",
+            percentile(&samples, 99.)
+        );
+        assert!(!check_slo(&samples, 100., budget));
+    }
+
+    #[test]
+    fn normal_quantiles() {
+        for (p, z) in [
+            (0.5, 0.),
+            (0.8, 0.8416212335729143),
+            (0.975, 1.959963984540054),
+            (0.995, 2.5758293035489004),
+            (0.01, -2.3263478740408408),
+        ] {
+            assert!((normal_quantile(p) - z).abs() < 1e-8, "z({p})");
+        }
+    }
+
+    #[test]
+    fn detectable_diff() {
+        // The textbook 2.8 standard deviations at 5% significance and 80% power with two runs each
+        let diff = min_detectable_diff(2, 1., 0.05, 0.8);
+        assert!((diff - 2.8016).abs() < 1e-4, "{diff}");
+        // Four times the runs halve the difference
+        let quadrupled = min_detectable_diff(8, 1., 0.05, 0.8);
+        assert!((diff / quadrupled - 2.).abs() < 1e-12);
+        assert_eq!(
+            min_detectable_diff(4, 300., 0.05, 0.8),
+            min_detectable_diff(4, 1., 0.05, 0.8) * 300.
+        );
+        assert!(min_detectable_diff(10, 1., 0.01, 0.8) > min_detectable_diff(10, 1., 0.05, 0.8));
+        assert!(min_detectable_diff(10, 1., 0.05, 0.9) > min_detectable_diff(10, 1., 0.05, 0.8));
+        assert_eq!(min_detectable_diff(0, 1., 0.05, 0.8), f64::INFINITY);
+    }
+
+    #[test]
+    fn welford_matches_batch() {
+        let data = [2., 4., 4., 4., 5., 5., 7., 9., 1e6, 1e6 + 1.];
+        let mut stats = RunningStats::new();
+        for x in data {
+            stats.push(x);
+        }
+
+        let n = data.len() as f64;
+        let mean = data.iter().sum::<f64>() / n;
+        let variance = data.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / (n - 1.);
+        assert_eq!(stats.count(), data.len() as u64);
+        assert!((stats.mean() - mean).abs() < 1e-9);
+        assert!((stats.variance() - variance).abs() / variance < 1e-9);
+        assert!((stats.stddev() - variance.sqrt()).abs() / variance.sqrt() < 1e-9);
+
+        let (left, right) = data.split_at(3);
+        let mut a = RunningStats::new();
+        left.iter().for_each(|x| a.push(*x));
+        let mut b = RunningStats::new();
+        right.iter().for_each(|x| b.push(*x));
+        let merged = a.merge(&b);
+        assert_eq!(merged.count(), stats.count());
+        assert!((merged.mean() - mean).abs() < 1e-9);
+        assert!((merged.variance() - variance).abs() / variance < 1e-9);
+    }
+
+    #[test]
+    fn ewma_converges() {
+        let mut ewma = EwmaThroughput::new(0.2);
+        assert_eq!(ewma.current(), 0.);
+
+        // Start far off, then alternate around 1000 tasks/sec
+        ewma.update(100, Duration::from_secs(1));
+        assert_eq!(ewma.current(), 100.);
+        for i in 0..64 {
+            let tasks = if i % 2 == 0 { 90 } else { 110 };
+            ewma.update(tasks, Duration::from_millis(100));
+        }
+        assert!((ewma.current() - 1000.).abs() < 50., "{}", ewma.current());
+
+        // A zero-length tick is skipped
+        let before = ewma.current();
+        ewma.update(10, Duration::ZERO);
+        assert_eq!(ewma.current(), before);
+    }
+
+    #[test]
+    fn wait_histogram() {
+        let mut histogram = WaitHistogram::new();
+        assert_eq!(histogram.percentile(50.), None);
+        histogram.record(Duration::ZERO);
+        for nanos in [1, 3, 100, 1000] {
+            histogram.record(Duration::from_nanos(nanos));
+        }
+        histogram.record(Duration::MAX);
+        assert_eq!(histogram.count(), 6);
+        assert_eq!(histogram.percentile(0.), Some(Duration::from_nanos(1)));
+        // 3 ns is in [2, 4)
+        assert_eq!(histogram.percentile(50.), Some(Duration::from_nanos(4)));
+        // 1000 ns is in [512, 1024)
+        assert_eq!(histogram.percentile(80.), Some(Duration::from_nanos(1024)));
+        assert_eq!(
+            histogram.percentile(100.),
+            Some(Duration::from_nanos(u64::MAX))
+        );
+
+        let merged = histogram.merge(&histogram);
+        assert_eq!(merged.count(), 12);
+        assert_eq!(merged.percentile(50.), histogram.percentile(50.));
+    }
+}
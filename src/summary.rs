@@ -0,0 +1,317 @@
+use std::time::Duration;
+
+use crate::lock_emulation::RunResult;
+
+/// Aggregate of the per-thread results of one parallel run
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParallelSummary {
+    pub threads: usize,
+    pub total_tasks: u64,
+    /// The longest elapsed time among the threads
+    pub max_elapsed: Duration,
+    /// Aggregate throughput by the [`AggregateMethod`] of the summary
+    pub tasks_per_sec: f64,
+    /// [`jain_fairness`] of the threads
+    pub fairness: f64,
+}
+
+/// How [`summarize_with`] aggregates the throughput of the threads
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AggregateMethod {
+    /// `total_tasks / max_elapsed`: what the whole run delivered over its wall-clock span
+    ///
+    /// Use it for capacity dashboards, where the question is how much work the system gets through per second.
+    /// Threads that finish early count as idle until the last one finishes, so it understates the rate of threads running side by side.
+    #[default]
+    MaxElapsed,
+    /// The sum of the `tasks_done / elapsed` of every thread: the combined rate while all threads run
+    ///
+    /// Use it for dashboards comparing locks or configs, where threads finishing at slightly different times should not weigh in.
+    SumOfRates,
+}
+
+/// Same as [`summarize_with`] by [`AggregateMethod::MaxElapsed`]
+pub fn summarize(results: &[RunResult]) -> ParallelSummary {
+    summarize_with(results, AggregateMethod::MaxElapsed)
+}
+
+pub fn summarize_with(results: &[RunResult], method: AggregateMethod) -> ParallelSummary {
+    let total_tasks = results.iter().map(|res| res.tasks_done).sum::<u64>();
+    let max_elapsed = results
+        .iter()
+        .map(|res| res.elapsed)
+        .max()
+        .unwrap_or_default();
+    ParallelSummary {
+        threads: results.len(),
+        total_tasks,
+        max_elapsed,
+        tasks_per_sec: match method {
+            AggregateMethod::MaxElapsed => total_tasks as f64 / max_elapsed.as_secs_f64(),
+            AggregateMethod::SumOfRates => results.iter().map(RunResult::tasks_per_sec).sum(),
+        },
+        fairness: jain_fairness(results),
+    }
+}
+
+/// Aggregate tasks/sec divided by the number of logical cores, for comparing machines of different sizes
+///
+/// Pass [`logical_cpus`] for the machine running the code, or override it with the core count the run was limited to.
+pub fn tasks_per_sec_per_core(summary: &ParallelSummary, logical_cpus: usize) -> f64 {
+    summary.tasks_per_sec / logical_cpus as f64
+}
+
+/// The logical cores available to this process; `1` if unknown
+pub fn logical_cpus() -> usize {
+    std::thread::available_parallelism().map_or(1, |cpus| cpus.get())
+}
+
+/// Jain's fairness index of the tasks done by each thread
+///
+/// `1.0` when every thread did the same amount of tasks, down to `1 / n` when a single thread did all of them.
+/// `1.0` for no threads or no tasks at all.
+pub fn jain_fairness(results: &[RunResult]) -> f64 {
+    let sum = results.iter().map(|res| res.tasks_done as f64).sum::<f64>();
+    let sum_of_squares = results
+        .iter()
+        .map(|res| (res.tasks_done as f64).powi(2))
+        .sum::<f64>();
+    if sum_of_squares == 0. {
+        return 1.;
+    }
+    sum * sum / (results.len() as f64 * sum_of_squares)
+}
+
+/// Total time each thread held the lock, in thread order, from [`RunResult::held`]
+///
+/// The thread with the largest total is the prime suspect for delaying the others, e.g. by convoying.
+/// [`toggle_lock_parallel`](crate::lock_emulation::toggle_lock_parallel) tracks the totals; see [`ToggleOptions::track_hold_time`](crate::lock_emulation::ToggleOptions::track_hold_time) for their overhead.
+/// `None` if any of `results` did not track its hold time.
+pub fn total_hold_by_thread(results: &[RunResult]) -> Option<Vec<Duration>> {
+    results.iter().map(|res| res.held).collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EwmaFairness {
+    /// Jain's fairness index of the smoothed shares after the last acquisition
+    pub last: f64,
+    /// The lowest index over the run
+    pub min: f64,
+}
+
+/// Jain's fairness index over time of the acquisition shares in `order`, as logged by [`ToggleOptions::acquisition_order`](crate::lock_emulation::ToggleOptions::acquisition_order)
+///
+/// Every thread starts with an equal share; each acquisition moves the shares `alpha` of the way toward all of it going to the acquiring thread.
+/// A lock that is fair on average but starves a thread now and then shows a good `last` and a low `min`.
+/// Both are `1.0` for an empty log.
+///
+/// # Panics
+///
+/// If `alpha` is not in `(0, 1]` or a thread index in `order` is not below `threads`.
+pub fn ewma_fairness(order: &[usize], threads: usize, alpha: f64) -> EwmaFairness {
+    assert!(0. < alpha && alpha <= 1., "alpha must be in (0, 1]");
+    let mut shares = vec![1. / threads as f64; threads];
+    let mut fairness = EwmaFairness { last: 1., min: 1. };
+    for &thread in order {
+        for share in &mut shares {
+            *share *= 1. - alpha;
+        }
+        shares[thread] += alpha;
+        let sum_of_squares = shares.iter().map(|share| share * share).sum::<f64>();
+        // The shares always sum up to one
+        fairness.last = 1. / (threads as f64 * sum_of_squares);
+        fairness.min = fairness.min.min(fairness.last);
+    }
+    fairness
+}
+
+/// Gini coefficient of the tasks done by each thread
+///
+/// `0.0` when every thread did the same amount of tasks, up to `(n - 1) / n` when a single thread did all of them.
+/// `0.0` for no threads or no tasks at all.
+pub fn gini_coefficient(results: &[RunResult]) -> f64 {
+    let mut tasks = results.iter().map(|res| res.tasks_done).collect::<Vec<_>>();
+    tasks.sort_unstable();
+    let n = tasks.len() as f64;
+    let sum = tasks.iter().map(|&tasks| tasks as f64).sum::<f64>();
+    if sum == 0. {
+        return 0.;
+    }
+    // With ascending `x_i` and 1-based `i`: `G = 2 * sum(i * x_i) / (n * sum(x)) - (n + 1) / n`
+    let weighted = tasks
+        .iter()
+        .enumerate()
+        .map(|(i, &tasks)| (i + 1) as f64 * tasks as f64)
+        .sum::<f64>();
+    2. * weighted / (n * sum) - (n + 1.) / n
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use crate::lock_emulation::{
+        toggle_lock_parallel, toggle_lock_parallel_with_options, ToggleOptions,
+    };
+
+    use super::*;
+
+    fn result(tasks_done: u64, elapsed: Duration) -> RunResult {
+        RunResult {
+            tasks_done,
+            elapsed,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn summary() {
+        let results = [
+            result(100, Duration::from_secs(1)),
+            result(300, Duration::from_secs(2)),
+        ];
+        let summary = summarize(&results);
+        assert_eq!(summary.threads, 2);
+        assert_eq!(summary.total_tasks, 400);
+        assert_eq!(summary.max_elapsed, Duration::from_secs(2));
+        assert_eq!(summary.tasks_per_sec, 200.);
+        assert!((summary.fairness - 0.8).abs() < 1e-12);
+    }
+
+    #[test]
+    fn aggregate_methods() {
+        let results = [
+            result(100, Duration::from_secs(1)),
+            result(300, Duration::from_secs(2)),
+        ];
+        let max_elapsed = summarize_with(&results, AggregateMethod::MaxElapsed);
+        let sum_of_rates = summarize_with(&results, AggregateMethod::SumOfRates);
+        assert_eq!(max_elapsed.tasks_per_sec, 200.);
+        assert_eq!(sum_of_rates.tasks_per_sec, 250.);
+        assert_eq!(max_elapsed.total_tasks, sum_of_rates.total_tasks);
+
+        // Same elapsed times, same throughput
+        let results = [
+            result(100, Duration::from_secs(2)),
+            result(300, Duration::from_secs(2)),
+        ];
+        assert_eq!(
+            summarize_with(&results, AggregateMethod::MaxElapsed).tasks_per_sec,
+            summarize_with(&results, AggregateMethod::SumOfRates).tasks_per_sec
+        );
+    }
+
+    #[test]
+    fn fairness_bounds() {
+        let equal = [result(5, Duration::ZERO), result(5, Duration::ZERO)];
+        assert_eq!(jain_fairness(&equal), 1.);
+        let hog = [
+            result(10, Duration::ZERO),
+            result(0, Duration::ZERO),
+            result(0, Duration::ZERO),
+            result(0, Duration::ZERO),
+        ];
+        assert_eq!(jain_fairness(&hog), 0.25);
+    }
+
+    #[test]
+    fn hold_blame() {
+        let held = |held| RunResult {
+            held,
+            ..Default::default()
+        };
+        let results = [
+            held(Some(Duration::from_millis(7))),
+            held(Some(Duration::ZERO)),
+        ];
+        assert_eq!(
+            total_hold_by_thread(&results),
+            Some(vec![Duration::from_millis(7), Duration::ZERO])
+        );
+        assert_eq!(
+            total_hold_by_thread(&[held(Some(Duration::ZERO)), held(None)]),
+            None
+        );
+
+        // Every thread of a real run holds the lock for part of it
+        let lock = Mutex::new(());
+        let res = toggle_lock_parallel(&lock, 1.0 / 2.0, 1.0 / 2.0, Duration::from_millis(200), 2);
+        let holds = total_hold_by_thread(&res).unwrap();
+        println!("Hold by thread: {holds:?}");
+        assert_eq!(holds.len(), 2);
+        assert!(holds.iter().all(|hold| !hold.is_zero()));
+        for (hold, res) in holds.iter().zip(&res) {
+            assert!(*hold <= res.elapsed);
+        }
+    }
+
+    #[test]
+    fn ewma_fairness_dips() {
+        let round_robin = [0, 1, 2, 3].repeat(50);
+        let fair = ewma_fairness(&round_robin, 4, 0.1);
+        assert!(fair.last > 0.95 && fair.min > 0.95, "{fair:?}");
+
+        // Thread 3 starved for a while in the middle, then back to round robin
+        let starving = [
+            [0, 1, 2, 3].repeat(50),
+            [0, 1, 2].repeat(20),
+            [0, 1, 2, 3].repeat(50),
+        ]
+        .concat();
+        let dip = ewma_fairness(&starving, 4, 0.1);
+        assert!(dip.last > 0.95, "{dip:?}");
+        assert!(dip.min < 0.8, "{dip:?}");
+
+        let hog = ewma_fairness(&[0; 100], 4, 0.5);
+        assert!((hog.last - 0.25).abs() < 1e-9);
+        assert_eq!(
+            ewma_fairness(&[], 4, 0.1),
+            EwmaFairness { last: 1., min: 1. }
+        );
+
+        let order = Arc::new(Mutex::new(vec![]));
+        let options = ToggleOptions {
+            acquisition_order: Some(Arc::clone(&order)),
+            ..Default::default()
+        };
+        toggle_lock_parallel_with_options(
+            &Mutex::new(()),
+            1.0 / 2.0,
+            1.0 / 2.0,
+            Duration::from_millis(200),
+            4,
+            &options,
+        );
+        let fairness = ewma_fairness(&order.lock().unwrap(), 4, 0.05);
+        println!("EWMA fairness of a mutex: {fairness:?}");
+        assert!(fairness.min <= fairness.last);
+    }
+
+    #[test]
+    fn gini_bounds() {
+        let equal = [result(5, Duration::ZERO), result(5, Duration::ZERO)];
+        assert!(gini_coefficient(&equal).abs() < 1e-12);
+        let hog = [
+            result(0, Duration::ZERO),
+            result(10, Duration::ZERO),
+            result(0, Duration::ZERO),
+            result(0, Duration::ZERO),
+        ];
+        assert!((gini_coefficient(&hog) - 0.75).abs() < 1e-12);
+        let mixed = [result(100, Duration::ZERO), result(300, Duration::ZERO)];
+        assert!((gini_coefficient(&mixed) - 0.25).abs() < 1e-12);
+        assert_eq!(gini_coefficient(&[]), 0.);
+        assert_eq!(gini_coefficient(&[result(0, Duration::ZERO)]), 0.);
+    }
+
+    #[test]
+    fn per_core() {
+        let results = [
+            result(100, Duration::from_secs(1)),
+            result(300, Duration::from_secs(2)),
+        ];
+        let summary = summarize(&results);
+        assert_eq!(tasks_per_sec_per_core(&summary, 4), 50.);
+        assert!(logical_cpus() >= 1);
+    }
+}
@@ -0,0 +1,218 @@
+//! Parameter sweeps and analysis of `(threads, aggregate tasks/sec)` curves
+
+use std::{sync::Mutex, time::Duration};
+
+use crate::{
+    config::SimConfig,
+    summary::{summarize, ParallelSummary},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GridCell {
+    pub config: SimConfig,
+    pub summary: ParallelSummary,
+}
+
+/// Run every `(lambda_unlock, lambda_lock)` pair at every thread count
+///
+/// Cells run one after another, each on a fresh lock, so they do not interfere with each other.
+pub fn run_grid(
+    lambdas: &[(f64, f64)],
+    thread_counts: &[usize],
+    duration_limit: Duration,
+) -> Vec<GridCell> {
+    let mut cells = vec![];
+    for &(lambda_unlock, lambda_lock) in lambdas {
+        for &threads in thread_counts {
+            let config =
+                SimConfig::new(lambda_unlock, lambda_lock, duration_limit).threads(threads);
+            let res = config.run(&Mutex::new(()));
+            cells.push(GridCell {
+                config,
+                summary: summarize(&res),
+            });
+        }
+    }
+    cells
+}
+
+/// Run every split `ratio` of a fixed `total_rate = lambda_lock + lambda_unlock`
+///
+/// Each `ratio` in `(0, 1)` sets `lambda_lock = ratio * total_rate` and `lambda_unlock` to the rest,
+/// which varies the hold fraction at a constant amount of toggling.
+/// Returns `(ratio, summary)` in the order of `ratios`.
+///
+/// # Panics
+///
+/// If a `ratio` is not in `(0, 1)`.
+pub fn sweep_ratio(
+    total_rate: f64,
+    ratios: &[f64],
+    duration_limit: Duration,
+    threads: usize,
+) -> Vec<(f64, ParallelSummary)> {
+    ratios
+        .iter()
+        .map(|&ratio| {
+            assert!(0. < ratio && ratio < 1., "ratio must be in (0, 1)");
+            let lambda_lock = ratio * total_rate;
+            let lambda_unlock = total_rate - lambda_lock;
+            let config =
+                SimConfig::new(lambda_unlock, lambda_lock, duration_limit).threads(threads);
+            let res = config.run(&Mutex::new(()));
+            (ratio, summarize(&res))
+        })
+        .collect()
+}
+
+/// `(threads, speedup)` relative to the 1-thread throughput
+///
+/// Returns `None` if `sweep` has no 1-thread data point.
+pub fn speedup_curve(sweep: &[(usize, f64)]) -> Option<Vec<(usize, f64)>> {
+    let (_, single) = sweep.iter().find(|(threads, _)| *threads == 1)?;
+    Some(
+        sweep
+            .iter()
+            .map(|(threads, tasks_per_sec)| (*threads, tasks_per_sec / single))
+            .collect(),
+    )
+}
+
+/// Change in throughput per doubling of threads, in percent of the throughput at the fewest threads
+///
+/// A least-squares line is fitted to `(log2(threads), tasks_per_sec)`, so thread counts need not be powers of two.
+/// The slope is divided by the fitted throughput at the smallest thread count, e.g. `-10.0` for a drop of 10% per doubling.
+/// NaN if `sweep` has fewer than two distinct thread counts.
+pub fn degradation_per_doubling(sweep: &[(usize, f64)]) -> f64 {
+    let points = sweep
+        .iter()
+        .map(|&(threads, tasks_per_sec)| ((threads as f64).log2(), tasks_per_sec))
+        .collect::<Vec<_>>();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>() / n;
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>() / n;
+    let covariance = points
+        .iter()
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum::<f64>();
+    let variance = points
+        .iter()
+        .map(|(x, _)| (x - mean_x).powi(2))
+        .sum::<f64>();
+    if variance == 0. {
+        return f64::NAN;
+    }
+    let slope = covariance / variance;
+    let min_x = points.iter().map(|(x, _)| *x).fold(f64::INFINITY, f64::min);
+    let baseline = mean_y + slope * (min_x - mean_x);
+    slope / baseline * 100.
+}
+
+/// The thread count of the highest aggregate tasks/sec in `sweep`, the smaller count on ties
+///
+/// Points of non-finite tasks/sec, e.g. from a run of zero elapsed time, are skipped.
+/// `0` if no point is left.
+pub fn optimal_thread_count(sweep: &[(usize, f64)]) -> usize {
+    let mut best: Option<(usize, f64)> = None;
+    for &(threads, tasks_per_sec) in sweep {
+        if !tasks_per_sec.is_finite() {
+            continue;
+        }
+        let better = match best {
+            None => true,
+            Some((best_threads, best_tasks_per_sec)) => {
+                tasks_per_sec > best_tasks_per_sec
+                    || (tasks_per_sec == best_tasks_per_sec && threads < best_threads)
+            }
+        };
+        if better {
+            best = Some((threads, tasks_per_sec));
+        }
+    }
+    best.map_or(0, |(threads, _)| threads)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speedup() {
+        let sweep = [(1, 100.), (2, 180.), (4, 200.), (8, 150.)];
+        let curve = speedup_curve(&sweep).unwrap();
+        assert_eq!(curve, vec![(1, 1.), (2, 1.8), (4, 2.), (8, 1.5)]);
+    }
+
+    #[test]
+    fn speedup_without_single_thread() {
+        let sweep = [(2, 180.), (4, 200.)];
+        assert_eq!(speedup_curve(&sweep), None);
+    }
+
+    #[test]
+    fn optimal_threads() {
+        let sweep = [(1, 100.), (2, 180.), (4, 220.), (8, 150.), (16, 90.)];
+        assert_eq!(optimal_thread_count(&sweep), 4);
+        let tie = [(8, 200.), (2, 150.), (4, 200.)];
+        assert_eq!(optimal_thread_count(&tie), 4);
+        assert_eq!(optimal_thread_count(&[]), 0);
+
+        // A run of zero elapsed time has NaN or infinite tasks/sec
+        let degenerate = [(1, f64::NAN), (2, 180.), (4, f64::INFINITY), (8, 150.)];
+        assert_eq!(optimal_thread_count(&degenerate), 2);
+        assert_eq!(optimal_thread_count(&[(1, f64::NAN)]), 0);
+    }
+
+    #[test]
+    fn per_doubling() {
+        // Drops by 100 tasks/sec from 1000 per doubling
+        let sweep =
+            [1, 2, 3, 4, 6, 8].map(|threads| (threads, 1000. - 100. * (threads as f64).log2()));
+        assert!((degradation_per_doubling(&sweep) + 10.).abs() < 1e-9);
+
+        // Relative to the fewest threads measured
+        let sweep = [(2, 800.), (4, 1000.), (8, 1200.)];
+        assert!((degradation_per_doubling(&sweep) - 25.).abs() < 1e-9);
+
+        assert!(degradation_per_doubling(&[(4, 100.), (4, 120.)]).is_nan());
+        assert!(degradation_per_doubling(&[]).is_nan());
+    }
+
+    #[test]
+    fn ratio_sweep() {
+        let ratios = [0.25, 0.5, 0.75];
+        let sweep = sweep_ratio(1.0, &ratios, Duration::from_millis(100), 2);
+
+        assert_eq!(sweep.len(), ratios.len());
+        for ((ratio, summary), expected) in sweep.iter().zip(ratios) {
+            assert_eq!(*ratio, expected);
+            assert_eq!(summary.threads, 2);
+            assert!(summary.total_tasks > 0);
+            println!("Ratio {ratio}: {:.02} tasks/sec", summary.tasks_per_sec);
+        }
+    }
+
+    #[test]
+    fn grid_2x2() {
+        let lambdas = [(1.0 / 2.0, 1.0 / 2.0), (1.0 / 2.0, 1.0 / 8.0)];
+        let thread_counts = [1, 2];
+        let duration_limit = Duration::from_millis(100);
+
+        let cells = run_grid(&lambdas, &thread_counts, duration_limit);
+
+        assert_eq!(cells.len(), 4);
+        for cell in &cells {
+            assert_eq!(cell.summary.threads, cell.config.threads);
+            assert!(cell.summary.total_tasks > 0);
+            println!(
+                "{}: {:.02} tasks/sec",
+                cell.config.describe(),
+                cell.summary.tasks_per_sec
+            );
+        }
+        assert_eq!(cells[1].config.lambda_lock, 1.0 / 2.0);
+        assert_eq!(cells[1].config.threads, 2);
+        assert_eq!(cells[2].config.lambda_lock, 1.0 / 8.0);
+        assert_eq!(cells[2].config.threads, 1);
+    }
+}
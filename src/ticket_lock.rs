@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Spins before yielding the time slice while waiting for a ticket
+const SPINS_PER_YIELD: usize = 64;
+
+/// A FIFO spin lock: threads are served strictly in the order they asked for the lock
+#[derive(Debug, Default)]
+pub struct TicketLock {
+    next: AtomicUsize,
+    serving: AtomicUsize,
+}
+
+impl TicketLock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a ticket and spin until it is served
+    ///
+    /// The waiter yields its time slice every so often so that an oversubscribed machine still makes progress.
+    /// The lock is unlocked when the returned guard is dropped.
+    pub fn lock(&self) -> TicketLockGuard<'_> {
+        let ticket = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut spins = 0;
+        while self.serving.load(Ordering::Acquire) != ticket {
+            spins += 1;
+            if spins % SPINS_PER_YIELD == 0 {
+                std::thread::yield_now();
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+        TicketLockGuard { lock: self }
+    }
+
+    fn unlock(&self) {
+        self.serving.fetch_add(1, Ordering::Release);
+    }
+}
+
+#[derive(Debug)]
+pub struct TicketLockGuard<'a> {
+    lock: &'a TicketLock,
+}
+
+impl Drop for TicketLockGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.unlock();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::UnsafeCell,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use crate::{
+        lock_emulation::{toggle_lock_parallel, toggle_lockable_parallel},
+        summary::summarize,
+    };
+
+    use super::*;
+
+    #[test]
+    fn mutual_exclusion() {
+        struct Shared(UnsafeCell<u64>);
+        unsafe impl Sync for Shared {}
+
+        let lock = Arc::new(TicketLock::new());
+        let shared = Arc::new(Shared(UnsafeCell::new(0)));
+        let threads = (0..4)
+            .map(|_| {
+                let lock = Arc::clone(&lock);
+                let shared = Arc::clone(&shared);
+                std::thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let _guard = lock.lock();
+                        unsafe { *shared.0.get() += 1 };
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(unsafe { *shared.0.get() }, 4000);
+    }
+
+    #[test]
+    fn fairness_against_mutex() {
+        let lambda_unlock = 1.0 / 2.0;
+        let lambda_lock = 1.0 / 2.0;
+        let duration_limit = Duration::from_millis(500);
+        let threads = 4;
+
+        let ticket = summarize(&toggle_lockable_parallel(
+            &TicketLock::new(),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+        ));
+        let mutex = summarize(&toggle_lock_parallel(
+            &Mutex::new(()),
+            lambda_unlock,
+            lambda_lock,
+            duration_limit,
+            threads,
+        ));
+        println!(
+            "Ticket lock: fairness {:.4}, {:.0} tasks/sec",
+            ticket.fairness, ticket.tasks_per_sec
+        );
+        println!(
+            "Mutex: fairness {:.4}, {:.0} tasks/sec",
+            mutex.fairness, mutex.tasks_per_sec
+        );
+        assert!(ticket.fairness > 0.9);
+    }
+}
@@ -0,0 +1,95 @@
+#![cfg(feature = "cli")]
+
+use assert_cmd::Command;
+
+fn run(format: &str) -> String {
+    let output = Command::cargo_bin("lock_contention")
+        .unwrap()
+        .args([
+            "--lambda-unlock",
+            "0.5",
+            "--lambda-lock",
+            "0.5",
+            "--duration-secs",
+            "0.2",
+            "--threads",
+            "2",
+            "--seed",
+            "42",
+            "--format",
+            format,
+        ])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    String::from_utf8(output).unwrap()
+}
+
+#[test]
+fn csv() {
+    let stdout = run("csv");
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].starts_with("thread,tasks_done,"));
+    assert!(lines[1].starts_with("0,"));
+    assert!(lines[2].starts_with("1,"));
+}
+
+#[test]
+fn json() {
+    let stdout = run("json");
+    assert!(stdout.trim().starts_with("[{"));
+    assert!(stdout.trim().ends_with("}]"));
+    assert_eq!(stdout.matches(r#""thread":"#).count(), 2);
+    assert_eq!(stdout.matches(r#""hostname":"#).count(), 2);
+}
+
+#[test]
+fn table() {
+    let stdout = run("table");
+    assert_eq!(stdout.lines().count(), 3);
+    assert!(stdout.contains("tasks/sec"));
+}
+
+#[test]
+fn rejects_unknown_format() {
+    Command::cargo_bin("lock_contention")
+        .unwrap()
+        .args(["--lambda-unlock", "0.5", "--lambda-lock", "0.5"])
+        .args(["--format", "xml"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn rejects_bad_duration() {
+    for duration in ["-1", "NaN", "1e300", "three"] {
+        Command::cargo_bin("lock_contention")
+            .unwrap()
+            .args(["--lambda-unlock", "0.5", "--lambda-lock", "0.5"])
+            .arg(format!("--duration-secs={duration}"))
+            .assert()
+            .failure()
+            .code(2);
+    }
+}
+
+#[test]
+fn json_of_zero_duration() {
+    let output = Command::cargo_bin("lock_contention")
+        .unwrap()
+        .args(["--lambda-unlock", "0.5", "--lambda-lock", "0.5"])
+        .args(["--duration-secs", "0", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output).unwrap();
+    assert!(
+        !stdout.contains("NaN") && !stdout.contains("inf"),
+        "{stdout}"
+    );
+}